@@ -1,5 +1,17 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::{Parser, Subcommand};
 
+use crate::{
+    backends::{BackendKind, FillMode},
+    constants::SOCKET_ENV,
+    utils::{
+        file_utils::{SortMode, DEFAULT_EXTENSIONS},
+        time_utils::parse_interval,
+    },
+    wpserver::server::SelectionMode,
+};
+
 #[derive(Clone, Debug, Subcommand)]
 pub enum Opt {
     /// Start the wallpaper server at a specified directory
@@ -11,34 +23,162 @@ pub enum Opt {
         #[arg(short = 'R', long, default_value_t = false)]
         recursive: bool,
 
-        /// Randomizes the order of pictures shown
-        #[arg(short = 'r', long, default_value_t = false)]
+        /// Randomizes the order of pictures shown. Same as `--selection shuffle`
+        #[arg(
+            short = 'r',
+            long,
+            default_value_t = false,
+            conflicts_with = "selection"
+        )]
         random: bool,
 
+        /// How the next wallpaper is picked
+        #[arg(long, value_enum, default_value_t = SelectionMode::Sequential)]
+        selection: SelectionMode,
+
+        /// Only pick up files matching this glob (relative to the directory, e.g. `landscape/**`). Can be repeated
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip files and directories matching this glob (relative to the directory, e.g. `drafts/**`). Can be repeated
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Order wallpapers are shown in with `--selection sequential`
+        #[arg(short, long, value_enum, default_value_t = SortMode::Natural)]
+        sort: SortMode,
+
         /// Redirect log output to log file
         #[arg(short = 'o', long)]
         log: Option<String>,
 
-        /// Time (in seconds) between automatic wallpaper updates
-        #[arg(short, long, default_value_t = 600)]
-        duration: u64,
+        /// File extensions that are considered images
+        #[arg(long, value_delimiter = ',', default_values = DEFAULT_EXTENSIONS)]
+        extensions: Vec<String>,
+
+        /// Rescan the directory every cycle instead of watching it for changes.
+        /// Network mounts are always rescanned, since changes made elsewhere can't be watched
+        #[arg(long, default_value_t = false)]
+        poll: bool,
+
+        /// Time between automatic wallpaper updates, in seconds or with a unit (e.g. `15m`, `1h 30m`)
+        #[arg(short, long, default_value = "600", value_parser = parse_interval)]
+        duration: Duration,
 
         /// Runs the wallpaper server in the current terminal (useful for debugging)
         #[arg(short, long = "foreground", default_value_t = false)]
         fg: bool,
+
+        /// Program used to actually set the wallpaper
+        #[arg(short, long, value_enum, default_value_t = BackendKind::Hyprpaper)]
+        backend: BackendKind,
+
+        /// How images are scaled to the screen (ignored by hyprpaper)
+        #[arg(short, long, value_enum, default_value_t = FillMode::Fill)]
+        mode: FillMode,
+
+        /// Transition effect used when changing wallpapers (swww only, e.g. `fade`, `wipe`, `grow`)
+        #[arg(long)]
+        transition_type: Option<String>,
+
+        /// Length of the transition in seconds (swww only)
+        #[arg(long)]
+        transition_duration: Option<f32>,
+
+        /// Frame rate of the transition (swww only)
+        #[arg(long)]
+        transition_fps: Option<u32>,
+
+        /// Command template for the `command` backend, e.g. `mysetter --mode fill {path}`.
+        /// `{path}` and `{output}` are substituted as single arguments, no shell is involved
+        #[arg(long, required_if_eq("backend", "command"))]
+        command: Option<String>,
+
+        /// Don't set any wallpapers, only log (and optionally record) what would have been set
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// File to append every wallpaper that would have been set to, as `<output>\t<path>` lines
+        #[arg(long, requires = "dry_run")]
+        record: Option<String>,
+
+        /// Output (monitor) to cycle wallpapers on. Can be repeated. By default, every output the backend reports is used
+        #[arg(long = "output")]
+        outputs: Vec<String>,
     },
 
     /// Manually update the wallpaper with a provided path
     Setwp {
         /// Path to wallpaper
         path: String,
+
+        /// Only update this output (monitor)
+        #[arg(long)]
+        output: Option<String>,
     },
 
     /// Print out the current wallpaper's path
-    Getwp,
+    Getwp {
+        /// Only print this output's (monitor's) wallpaper
+        #[arg(long)]
+        output: Option<String>,
+    },
 
     /// Cycle to the next wallpaper in the queue
-    Next,
+    Next {
+        /// Only cycle this output (monitor)
+        #[arg(long)]
+        output: Option<String>,
+    },
+
+    /// Stop changing the wallpaper automatically. `next`, `prev` and `setwp` still work
+    Pause {
+        /// Resume on its own after this long, e.g. `30m` or `1h 30m`
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+
+    /// Start changing the wallpaper automatically again
+    Resume,
+
+    /// Pause if running, resume if paused
+    Toggle,
+
+    /// Change the time between automatic wallpaper updates
+    #[command(alias = "setduration")]
+    SetDuration {
+        /// New time between updates, in seconds or with a unit (e.g. `15m`, `1h 30m`)
+        #[arg(value_parser = parse_interval)]
+        duration: Duration,
+    },
+
+    /// Print out the time between automatic wallpaper updates
+    #[command(alias = "getduration")]
+    GetDuration,
+
+    /// Go back to the previously shown wallpaper
+    Prev {
+        /// Only go back on this output (monitor)
+        #[arg(long)]
+        output: Option<String>,
+    },
+
+    /// Print out previously shown wallpapers, most recent first
+    History {
+        /// How many wallpapers to print
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+
+        /// Only print this output's (monitor's) history
+        #[arg(long)]
+        output: Option<String>,
+    },
+
+    /// Print out everything the server is doing
+    Status,
+
+    /// Print events from the server (wallpaper changes, pauses, errors...) as they happen, until it stops
+    Watch,
 
     /// Print out the current wallpaper directory
     Getdir,
@@ -52,9 +192,30 @@ pub enum Opt {
         #[arg(short = 'R', long, default_value_t = false)]
         recursive: bool,
 
-        /// Randomizes the order of pictures shown
-        #[arg(short = 'r', long, default_value_t = false)]
+        /// Randomizes the order of pictures shown. Same as `--selection shuffle`
+        #[arg(
+            short = 'r',
+            long,
+            default_value_t = false,
+            conflicts_with = "selection"
+        )]
         random: bool,
+
+        /// How the next wallpaper is picked
+        #[arg(long, value_enum, default_value_t = SelectionMode::Sequential)]
+        selection: SelectionMode,
+
+        /// Only pick up files matching this glob (relative to the directory, e.g. `landscape/**`). Can be repeated
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip files and directories matching this glob (relative to the directory, e.g. `drafts/**`). Can be repeated
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Order wallpapers are shown in with `--selection sequential`. Keeps the current order if not given
+        #[arg(short, long, value_enum)]
+        sort: Option<SortMode>,
     },
 
     /// Ping the wallpaper server
@@ -69,4 +230,12 @@ pub enum Opt {
 pub struct Args {
     #[command(subcommand)]
     pub command: Opt,
+
+    /// Print replies as JSON objects with `status`, `message` and `data` fields
+    #[arg(long, global = true, default_value_t = false)]
+    pub json: bool,
+
+    /// Socket the server listens on. Defaults to `$XDG_RUNTIME_DIR/wonderwall.sock`
+    #[arg(long, global = true, env = SOCKET_ENV)]
+    pub socket: Option<PathBuf>,
 }
@@ -0,0 +1,59 @@
+use std::process::Command;
+
+use super::{run_command, BackendError, WallpaperBackend};
+
+/// Placeholder replaced with the wallpaper's path
+pub const PATH_PLACEHOLDER: &str = "{path}";
+
+/// Placeholder replaced with the output (monitor) name. Empty when the wallpaper is meant for every output
+pub const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+/// Runs a user supplied command template, e.g. `mysetter --output {output} --mode fill {path}`.
+///
+/// The template is split into arguments once (with shell-like quoting rules) and placeholders are
+/// substituted into each argument separately. Nothing is ever passed through a shell, so paths
+/// containing spaces, quotes or `$` end up as a single, literal argument.
+pub struct CommandBackend {
+    argv: Vec<String>,
+}
+
+impl CommandBackend {
+    pub fn new(template: &str) -> Result<Self, BackendError> {
+        let argv = shlex::split(template)
+            .ok_or_else(|| BackendError(format!("Unbalanced quotes in command `{template}`")))?;
+
+        if argv.is_empty() {
+            return Err(BackendError("Command template is empty".to_string()));
+        }
+        if !argv.iter().any(|arg| arg.contains(PATH_PLACEHOLDER)) {
+            return Err(BackendError(format!(
+                "Command template must contain {PATH_PLACEHOLDER}"
+            )));
+        }
+
+        Ok(Self { argv })
+    }
+
+    /// Builds the command for a wallpaper by substituting the placeholders in each argument
+    pub(crate) fn build(&self, path: &str, output: &str) -> Command {
+        let mut args = self.argv.iter().map(|arg| {
+            arg.replace(PATH_PLACEHOLDER, path)
+                .replace(OUTPUT_PLACEHOLDER, output)
+        });
+
+        // `new` made sure there's at least one argument
+        let mut command = Command::new(args.next().unwrap());
+        command.args(args);
+        command
+    }
+}
+
+impl WallpaperBackend for CommandBackend {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        run_command(&mut self.build(path, output)).map(|_| ())
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+use super::{BackendError, WallpaperBackend};
+
+/// How long we wait on hyprpaper before assuming it's hung
+const HYPRPAPER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// hyprpaper's IPC socket
+const HYPRPAPER_SOCKET: &str = ".hyprpaper.sock";
+
+/// Hyprland's own request socket, used to list monitors
+const HYPRLAND_SOCKET: &str = ".socket.sock";
+
+/// Talks to hyprpaper directly over its IPC socket, the same way `hyprctl hyprpaper ...` does
+pub struct HyprpaperBackend;
+
+impl HyprpaperBackend {
+    /// Finds one of the current Hyprland instance's sockets (`.hyprpaper.sock` or Hyprland's own `.socket.sock`).
+    ///
+    /// Newer versions of Hyprland keep them in `$XDG_RUNTIME_DIR/hypr/`, older ones in `/tmp/hypr/`
+    fn socket_path(&self, socket_name: &str) -> Result<PathBuf, BackendError> {
+        let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|_| {
+            BackendError("HYPRLAND_INSTANCE_SIGNATURE is not set. Is Hyprland running?".to_string())
+        })?;
+
+        let mut candidates = vec![];
+        if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+            candidates.push(PathBuf::from(runtime_dir).join("hypr"));
+        }
+        candidates.push(PathBuf::from("/tmp/hypr"));
+
+        candidates
+            .into_iter()
+            .map(|dir| dir.join(&signature).join(socket_name))
+            .find(|path| path.exists())
+            .ok_or_else(|| BackendError(format!("Could not find {socket_name}. Is it running?")))
+    }
+
+    /// Sends a single request to one of Hyprland's sockets and returns its reply
+    fn request(&self, socket_name: &str, request: &str) -> Result<String, BackendError> {
+        let socket = self.socket_path(socket_name)?;
+        log::info!("Sending {socket_name} request: `{request}`");
+
+        let mut stream = UnixStream::connect(&socket)
+            .map_err(|e| BackendError(format!("Could not connect to {}: {e}", socket.display())))?;
+        stream
+            .set_read_timeout(Some(HYPRPAPER_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(HYPRPAPER_TIMEOUT)))
+            .and_then(|_| stream.write_all(request.as_bytes()))
+            .map_err(|e| BackendError(format!("Could not send request to {socket_name}: {e}")))?;
+
+        // Both hyprpaper and Hyprland close the connection once they've replied
+        let mut reply = String::new();
+        stream
+            .read_to_string(&mut reply)
+            .map_err(|e| BackendError(format!("Could not read reply from {socket_name}: {e}")))?;
+
+        Ok(reply)
+    }
+
+    /// Sends a request that hyprpaper should answer with `ok`
+    fn command(&self, request: &str) -> Result<(), BackendError> {
+        let reply = self.request(HYPRPAPER_SOCKET, request)?;
+        if reply.trim() != "ok" {
+            return Err(BackendError(format!(
+                "hyprpaper rejected `{request}`: {}",
+                reply.trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl WallpaperBackend for HyprpaperBackend {
+    fn name(&self) -> &'static str {
+        "hyprpaper"
+    }
+
+    fn outputs(&mut self) -> Result<Vec<String>, BackendError> {
+        let reply = self.request(HYPRLAND_SOCKET, "monitors")?;
+        Ok(parse_monitors(&reply))
+    }
+
+    fn health_check(&mut self) -> Result<(), BackendError> {
+        self.request(HYPRPAPER_SOCKET, "listloaded").map(|_| ())
+    }
+
+    fn preload(&mut self, path: &str) -> Result<(), BackendError> {
+        self.command(&format!("preload {path}"))
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        // hyprpaper splits on the first comma, so commas in the path itself are fine.
+        // An empty monitor (`ALL_OUTPUTS`) means every monitor
+        self.command(&format!("wallpaper {output},{path}"))
+    }
+
+    fn unload(&mut self) -> Result<(), BackendError> {
+        self.command("unload unused")
+    }
+}
+
+/// Reads the monitor names out of Hyprland's `monitors` reply, which has a block per monitor, each
+/// starting with `Monitor <name> (ID <n>):`
+pub(crate) fn parse_monitors(reply: &str) -> Vec<String> {
+    reply
+        .lines()
+        .filter_map(|line| line.strip_prefix("Monitor "))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
@@ -0,0 +1,167 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use clap::ValueEnum;
+
+pub mod command;
+pub mod hyprpaper;
+pub mod recording;
+pub mod swaybg;
+pub mod swww;
+pub mod x11;
+
+#[cfg(test)]
+mod tests;
+
+/// Error returned by a backend when the underlying wallpaper setter misbehaves
+#[derive(Debug)]
+pub struct BackendError(pub String);
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl Error for BackendError {}
+
+/// Output name that stands for every output at once. Used when the backend can't tell outputs apart
+pub const ALL_OUTPUTS: &str = "";
+
+/// Something that knows how to actually put an image on the screen.
+///
+/// The server only ever talks to a `WallpaperBackend`, so supporting a new wallpaper setter
+/// only requires implementing this trait and adding it to `BackendKind`.
+pub trait WallpaperBackend: Send {
+    /// Name of the backend, used in logs
+    fn name(&self) -> &'static str;
+
+    /// Lists the outputs (monitors) the backend can set wallpapers on. An empty list means the
+    /// backend can't target outputs individually, so everything goes through `ALL_OUTPUTS`
+    fn outputs(&mut self) -> Result<Vec<String>, BackendError> {
+        Ok(vec![])
+    }
+
+    /// Checks whether the backend is usable (e.g. the daemon it talks to is alive)
+    fn health_check(&mut self) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Loads an image ahead of time. Backends that don't need this can leave it as a no-op
+    fn preload(&mut self, _path: &str) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Displays an image on an output, or on every output if `output` is `ALL_OUTPUTS`
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError>;
+
+    /// Frees any images that aren't being displayed anymore
+    fn unload(&mut self) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Cleans up anything the backend started (e.g. child processes) when the server stops. The
+    /// backend won't be dropped before the process exits, so this can't be left to `Drop`
+    fn shutdown(&mut self) {}
+}
+
+/// Backend shared between the socket listener and the cycling thread
+pub type SharedBackend = Arc<Mutex<Box<dyn WallpaperBackend>>>;
+
+/// Backends the user can pick from when starting the server
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum BackendKind {
+    /// Hyprland's hyprpaper
+    #[default]
+    Hyprpaper,
+    /// swww (works on any wlroots compositor, supports transitions)
+    Swww,
+    /// swaybg, kept running as a child process
+    Swaybg,
+    /// feh, for X11 window managers
+    Feh,
+    /// xwallpaper, for X11 window managers
+    Xwallpaper,
+    /// Any program, run through the template given with `--command`
+    Command,
+    /// Doesn't set anything, only records. Picked with `--dry-run`
+    #[value(skip)]
+    DryRun,
+}
+
+/// How an image should be scaled to the screen. Each backend maps this to its own flags
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum FillMode {
+    /// Scale and crop to cover the whole screen
+    #[default]
+    Fill,
+    /// Scale to fit inside the screen, keeping the aspect ratio
+    Fit,
+    /// Center without scaling
+    Center,
+    /// Stretch to the screen's size, ignoring the aspect ratio
+    Stretch,
+    /// Repeat the image across the screen
+    Tile,
+}
+
+/// Backend specific options the user can pass in when starting the server
+#[derive(Clone, Debug, Default)]
+pub struct BackendOptions {
+    pub mode: FillMode,
+    pub transition_type: Option<String>,
+    pub transition_duration: Option<f32>,
+    pub transition_fps: Option<u32>,
+    pub command: Option<String>,
+    pub record: Option<String>,
+}
+
+impl BackendKind {
+    /// Creates the backend, failing if the options it needs are missing or invalid
+    pub fn create(
+        self,
+        options: BackendOptions,
+    ) -> Result<Box<dyn WallpaperBackend>, BackendError> {
+        Ok(match self {
+            BackendKind::Hyprpaper => Box::new(hyprpaper::HyprpaperBackend),
+            BackendKind::Swww => Box::new(swww::SwwwBackend::new(options)),
+            BackendKind::Swaybg => Box::new(swaybg::SwaybgBackend::new(options)),
+            BackendKind::Feh => Box::new(x11::X11Backend::new(x11::X11Setter::Feh, options)),
+            BackendKind::Xwallpaper => {
+                Box::new(x11::X11Backend::new(x11::X11Setter::Xwallpaper, options))
+            }
+            BackendKind::Command => {
+                let Some(template) = options.command else {
+                    return Err(BackendError(
+                        "The command backend requires --command".to_string(),
+                    ));
+                };
+                Box::new(command::CommandBackend::new(&template)?)
+            }
+            BackendKind::DryRun => {
+                Box::new(recording::RecordingBackend::new(options.record.as_deref())?)
+            }
+        })
+    }
+}
+
+/// Runs a command to completion, turning a non-zero exit status into a `BackendError` containing stderr
+pub fn run_command(command: &mut Command) -> Result<String, BackendError> {
+    log::info!("Executing command: `{:?}`", &command);
+    let output = command
+        .output()
+        .map_err(|e| BackendError(format!("Could not run {:?}: {e}", command.get_program())))?;
+
+    if !output.status.success() {
+        return Err(BackendError(format!(
+            "{:?} exited with {}: {}",
+            command.get_program(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs::File,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use super::{BackendError, WallpaperBackend, ALL_OUTPUTS};
+
+/// Wallpapers a `RecordingBackend` applied so far, as `(output, path)` pairs
+pub type Recording = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Never touches the screen, only remembers every wallpaper it was asked to apply. Optionally
+/// appends them to a file as `<output>\t<path>` lines (`*` being every output).
+///
+/// Used by `start --dry-run` and by the tests
+pub struct RecordingBackend {
+    applied: Recording,
+    file: Option<File>,
+}
+
+impl RecordingBackend {
+    pub fn new(file: Option<&str>) -> Result<Self, BackendError> {
+        let file = file
+            .map(|path| {
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| BackendError(format!("Could not open {path}: {e}")))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            applied: Arc::new(Mutex::new(vec![])),
+            file,
+        })
+    }
+
+    /// Handle to everything this backend applies, which stays valid after the backend is moved into a server
+    #[cfg(test)]
+    pub fn recording(&self) -> Recording {
+        self.applied.clone()
+    }
+}
+
+impl WallpaperBackend for RecordingBackend {
+    fn name(&self) -> &'static str {
+        "dry-run"
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        if let Some(file) = &mut self.file {
+            let output = match output {
+                ALL_OUTPUTS => "*",
+                name => name,
+            };
+            writeln!(file, "{output}\t{path}").map_err(|e| BackendError(e.to_string()))?;
+        }
+
+        self.applied
+            .lock()
+            .unwrap()
+            .push((output.to_string(), path.to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::BTreeMap,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use super::{run_command, BackendError, BackendOptions, FillMode, WallpaperBackend, ALL_OUTPUTS};
+
+/// How long a freshly spawned swaybg gets to draw before the old one is killed. Killing the old one
+/// right away would briefly flash the compositor's background color
+const SWAYBG_STARTUP_TIME: Duration = Duration::from_millis(250);
+
+/// Keeps a `swaybg` child process alive and replaces it every time the wallpaper changes.
+///
+/// A single swaybg draws every output, so we remember what each output is showing and pass all of it
+/// to the new process
+pub struct SwaybgBackend {
+    options: BackendOptions,
+    child: Option<Child>,
+    wallpapers: BTreeMap<String, String>,
+    /// Set by `shutdown()`, so a cycle that was already underway doesn't start a new swaybg
+    stopped: bool,
+}
+
+impl SwaybgBackend {
+    pub fn new(options: BackendOptions) -> Self {
+        Self {
+            options,
+            child: None,
+            wallpapers: BTreeMap::new(),
+            stopped: false,
+        }
+    }
+
+    /// Kills the currently running swaybg (if any) and reaps it
+    fn stop_child(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            if let Err(e) = child.kill() {
+                log::warn!("Failed to kill swaybg (pid {}): {e}", child.id());
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+impl WallpaperBackend for SwaybgBackend {
+    fn name(&self) -> &'static str {
+        "swaybg"
+    }
+
+    fn health_check(&mut self) -> Result<(), BackendError> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return Err(BackendError("WAYLAND_DISPLAY is not set".to_string()));
+        }
+
+        // If we already have a child, it should still be alive
+        if let Some(child) = &mut self.child {
+            if let Ok(Some(status)) = child.try_wait() {
+                return Err(BackendError(format!("swaybg exited with {status}")));
+            }
+            return Ok(());
+        }
+
+        run_command(Command::new("swaybg").arg("--version")).map(|_| ())
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        if self.stopped {
+            return Err(BackendError("swaybg was shut down".to_string()));
+        }
+
+        let mode = match self.options.mode {
+            FillMode::Fill => "fill",
+            FillMode::Fit => "fit",
+            FillMode::Center => "center",
+            FillMode::Stretch => "stretch",
+            FillMode::Tile => "tile",
+        };
+
+        // A wallpaper for every output replaces whatever the individual outputs were showing
+        if output == ALL_OUTPUTS {
+            self.wallpapers.clear();
+        }
+        let mut wallpapers = self.wallpapers.clone();
+        wallpapers.insert(output.to_string(), path.to_string());
+
+        let mut command = Command::new("swaybg");
+        for (output, path) in wallpapers.iter() {
+            let output = match output.as_str() {
+                ALL_OUTPUTS => "*",
+                name => name,
+            };
+            command.args(["--output", output, "--image", path, "--mode", mode]);
+        }
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log::info!("Spawning: `{:?}`", &command);
+
+        let mut new_child = command
+            .spawn()
+            .map_err(|e| BackendError(format!("Could not spawn swaybg: {e}")))?;
+
+        // Give the new swaybg a moment to draw, and make sure it didn't immediately die (e.g. unreadable image)
+        std::thread::sleep(SWAYBG_STARTUP_TIME);
+        if let Ok(Some(status)) = new_child.try_wait() {
+            return Err(BackendError(format!("swaybg exited with {status}")));
+        }
+
+        self.stop_child();
+        self.child = Some(new_child);
+        self.wallpapers = wallpapers;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        self.stopped = true;
+        self.stop_child();
+    }
+}
+
+impl Drop for SwaybgBackend {
+    fn drop(&mut self) {
+        self.stop_child();
+    }
+}
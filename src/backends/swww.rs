@@ -0,0 +1,68 @@
+use std::process::Command;
+
+use super::{run_command, BackendError, BackendOptions, FillMode, WallpaperBackend, ALL_OUTPUTS};
+
+/// Drives the swww daemon through `swww img`
+pub struct SwwwBackend {
+    options: BackendOptions,
+}
+
+impl SwwwBackend {
+    pub fn new(options: BackendOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl WallpaperBackend for SwwwBackend {
+    fn name(&self) -> &'static str {
+        "swww"
+    }
+
+    fn outputs(&mut self) -> Result<Vec<String>, BackendError> {
+        let stdout = run_command(Command::new("swww").arg("query"))?;
+        Ok(parse_outputs(&stdout))
+    }
+
+    fn health_check(&mut self) -> Result<(), BackendError> {
+        run_command(Command::new("swww").arg("query")).map(|_| ())
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        // swww can't stretch or tile, so those fall back to the closest thing it can do
+        let resize = match self.options.mode {
+            FillMode::Fill | FillMode::Stretch => "crop",
+            FillMode::Fit => "fit",
+            FillMode::Center | FillMode::Tile => "no",
+        };
+
+        let mut command = Command::new("swww");
+        command.arg("img").arg(path).args(["--resize", resize]);
+
+        if output != ALL_OUTPUTS {
+            command.args(["--outputs", output]);
+        }
+
+        if let Some(transition_type) = &self.options.transition_type {
+            command.args(["--transition-type", transition_type]);
+        }
+        if let Some(duration) = self.options.transition_duration {
+            command.args(["--transition-duration", &duration.to_string()]);
+        }
+        if let Some(fps) = self.options.transition_fps {
+            command.args(["--transition-fps", &fps.to_string()]);
+        }
+
+        run_command(&mut command).map(|_| ())
+    }
+}
+
+/// Reads the output names out of `swww query`. Each line looks like
+/// `DP-1: 1920x1080, scale: 1, currently displaying: ...`. Newer versions of swww prefix it with `: `
+pub(crate) fn parse_outputs(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim_start_matches([':', ' ']).split_once(':'))
+        .map(|(name, _)| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
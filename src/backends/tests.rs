@@ -0,0 +1,101 @@
+use std::ffi::OsStr;
+
+use super::{command::CommandBackend, hyprpaper, swww, x11};
+
+/// Program and arguments the command backend would run for `path` on `output`
+fn argv(template: &str, path: &str, output: &str) -> Vec<String> {
+    let command = CommandBackend::new(template).unwrap().build(path, output);
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(OsStr::to_string_lossy)
+        .map(|arg| arg.into_owned())
+        .collect()
+}
+
+#[test]
+fn command_paths_are_passed_as_a_single_literal_argument() {
+    for path in [
+        "/walls/two words.png",
+        "/walls/it's.png",
+        "/walls/\"quoted\".png",
+        "/walls/$HOME.png",
+        "/walls/a.png; rm -rf ~",
+        "/walls/$(reboot) `reboot`.png",
+    ] {
+        assert_eq!(
+            argv("mysetter --mode fill {path}", path, ""),
+            vec!["mysetter", "--mode", "fill", path]
+        );
+    }
+}
+
+#[test]
+fn command_templates_are_split_once_and_substituted() {
+    assert_eq!(
+        argv(
+            "'my setter' --output {output} --image=\"{path}\"",
+            "/walls/a b.png",
+            "DP-1"
+        ),
+        vec!["my setter", "--output", "DP-1", "--image=/walls/a b.png"]
+    );
+
+    // Every output at once leaves an empty argument rather than dropping it
+    assert_eq!(
+        argv("mysetter {output} {path}", "/walls/a.png", ""),
+        vec!["mysetter", "", "/walls/a.png"]
+    );
+}
+
+#[test]
+fn bad_command_templates_are_rejected() {
+    for template in [
+        "",
+        "mysetter --mode fill",
+        "mysetter 'unbalanced {path}",
+        "mysetter \"{path}",
+    ] {
+        assert!(
+            CommandBackend::new(template).is_err(),
+            "`{template}` was accepted"
+        );
+    }
+}
+
+#[test]
+fn swww_outputs_are_read_from_query() {
+    let old = "DP-1: 2560x1440, scale: 1, currently displaying: image: /walls/a.png\n\
+               HDMI-A-1: 1920x1080, scale: 1, currently displaying: color: 000000\n";
+    assert_eq!(swww::parse_outputs(old), vec!["DP-1", "HDMI-A-1"]);
+
+    let new = ": DP-1: 2560x1440, scale: 1, currently displaying: image: /walls/a.png\n\
+               : eDP-1: 1920x1200, scale: 1.25, currently displaying: image: /walls/b.png\n";
+    assert_eq!(swww::parse_outputs(new), vec!["DP-1", "eDP-1"]);
+
+    assert!(swww::parse_outputs("").is_empty());
+}
+
+#[test]
+fn xrandr_outputs_are_read_from_listactivemonitors() {
+    let stdout = "Monitors: 2\n \
+                  0: +*DP-1 2560/597x1440/336+0+0  DP-1\n \
+                  1: +HDMI-1 1920/527x1080/296+2560+0  HDMI-1\n";
+    assert_eq!(x11::parse_outputs(stdout), vec!["DP-1", "HDMI-1"]);
+
+    assert!(x11::parse_outputs("Monitors: 0\n").is_empty());
+}
+
+#[test]
+fn hyprland_monitors_are_read_from_its_reply() {
+    let reply = "Monitor DP-1 (ID 0):\n\
+                 \t2560x1440@143.99800 at 0x0\n\
+                 \tdescription: Dell Inc. DELL S2721DGF\n\
+                 \tfocused: yes\n\
+                 \n\
+                 Monitor HDMI-A-1 (ID 1):\n\
+                 \t1920x1080@60.00000 at 2560x0\n\
+                 \tdescription: Monitor HDMI-A-1 (fake)\n";
+    assert_eq!(hyprpaper::parse_monitors(reply), vec!["DP-1", "HDMI-A-1"]);
+
+    assert!(hyprpaper::parse_monitors("").is_empty());
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeMap, process::Command};
+
+use super::{run_command, BackendError, BackendOptions, FillMode, WallpaperBackend, ALL_OUTPUTS};
+
+/// Programs that can set the X root window's pixmap
+#[derive(Clone, Copy, Debug)]
+pub enum X11Setter {
+    Feh,
+    Xwallpaper,
+}
+
+/// Sets the X11 root window background through `feh` or `xwallpaper`. Both of them also set
+/// `_XROOTPMAP_ID`, so compositors and pseudo-transparent terminals pick up the change
+pub struct X11Backend {
+    setter: X11Setter,
+    options: BackendOptions,
+    /// What each output is showing. The root pixmap is redrawn from scratch on every change, so
+    /// xwallpaper has to be told about every output each time
+    wallpapers: BTreeMap<String, String>,
+}
+
+impl X11Backend {
+    pub fn new(setter: X11Setter, options: BackendOptions) -> Self {
+        Self {
+            setter,
+            options,
+            wallpapers: BTreeMap::new(),
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self.setter {
+            X11Setter::Feh => "feh",
+            X11Setter::Xwallpaper => "xwallpaper",
+        }
+    }
+}
+
+impl WallpaperBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        self.program()
+    }
+
+    fn outputs(&mut self) -> Result<Vec<String>, BackendError> {
+        // feh can only spread images across screens in Xinerama order, so it doesn't do per-output
+        if let X11Setter::Feh = self.setter {
+            return Ok(vec![]);
+        }
+
+        let stdout = run_command(Command::new("xrandr").arg("--listactivemonitors"))?;
+        Ok(parse_outputs(&stdout))
+    }
+
+    fn health_check(&mut self) -> Result<(), BackendError> {
+        if std::env::var_os("DISPLAY").is_none() {
+            return Err(BackendError("DISPLAY is not set".to_string()));
+        }
+        run_command(Command::new(self.program()).arg("--version")).map(|_| ())
+    }
+
+    fn apply(&mut self, output: &str, path: &str) -> Result<(), BackendError> {
+        let mut command = Command::new(self.program());
+        match self.setter {
+            X11Setter::Feh => {
+                let flag = match self.options.mode {
+                    FillMode::Fill => "--bg-fill",
+                    FillMode::Fit => "--bg-max",
+                    FillMode::Center => "--bg-center",
+                    FillMode::Stretch => "--bg-scale",
+                    FillMode::Tile => "--bg-tile",
+                };
+                // `--no-fehbg` keeps feh from rewriting ~/.fehbg on every change
+                command.args(["--no-fehbg", flag, path]);
+                run_command(&mut command).map(|_| ())
+            }
+            X11Setter::Xwallpaper => {
+                let flag = match self.options.mode {
+                    FillMode::Fill => "--zoom",
+                    FillMode::Fit => "--maximize",
+                    FillMode::Center => "--center",
+                    FillMode::Stretch => "--stretch",
+                    FillMode::Tile => "--tile",
+                };
+
+                if output == ALL_OUTPUTS {
+                    self.wallpapers.clear();
+                }
+                let mut wallpapers = self.wallpapers.clone();
+                wallpapers.insert(output.to_string(), path.to_string());
+
+                for (output, path) in wallpapers.iter() {
+                    if output != ALL_OUTPUTS {
+                        command.args(["--output", output]);
+                    }
+                    command.args([flag, path]);
+                }
+
+                run_command(&mut command)?;
+                self.wallpapers = wallpapers;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads the output names out of `xrandr --listactivemonitors`. The first line is a `Monitors: N`
+/// header, the rest end with the output's name
+pub(crate) fn parse_outputs(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().last())
+        .map(|name| name.to_string())
+        .collect()
+}
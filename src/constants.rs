@@ -1,6 +1,42 @@
+use std::time::Duration;
+
 /// Static error message used when writing to socket stream fails.
 pub const SOCKET_WRITE_ERROR: &str = "Failed to write to File Socket Stream!";
 
-/// Default/built-in socket file path to use. Feel free to change this if you for some reason have one already
-/// or if you don't want to keep it in `/tmp/`
-pub const FILE_SOCKET: &str = "/tmp/wonderwall.sock";
+/// Name of the socket file, which goes in `$XDG_RUNTIME_DIR` unless `--socket` says otherwise
+pub const SOCKET_FILE_NAME: &str = "wonderwall.sock";
+
+/// Environment variable that sets the socket path, same as `--socket`
+pub const SOCKET_ENV: &str = "WONDERWALL_SOCKET";
+
+/// Largest request/response line plus headers we'll read before giving up on a packet
+pub const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// Largest body we'll read, going by its `Content-Length`
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// How long the server waits on a client to send its whole request (or read the reply) before giving up on it
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most connections the server handles at once. Any more are turned away with a 503
+pub const MAX_CONNECTIONS: usize = 32;
+
+/// Most connections subscribed to events at once. They don't count towards `MAX_CONNECTIONS`,
+/// since they stay open for as long as the client wants
+pub const MAX_SUBSCRIBERS: usize = 16;
+
+/// How long a client waits for the server's reply. Generous, since `SETDIR` has to scan the new directory
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Exit code of a client command the server rejected, e.g. a bad path or unknown output (4xx replies)
+pub const EXIT_REJECTED: u8 = 1;
+
+/// Exit code of a client command the server failed to carry out (5xx or any other non-2xx reply)
+pub const EXIT_SERVER_ERROR: u8 = 3;
+
+/// Exit code when the server couldn't be reached, most likely because it isn't running.
+/// `2` is skipped since clap already uses it for usage errors
+pub const EXIT_CONNECTION_ERROR: u8 = 4;
+
+/// Exit code when the server's reply couldn't be understood
+pub const EXIT_PROTOCOL_ERROR: u8 = 5;
@@ -1,23 +1,41 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    io::{BufRead, Read},
+    path::Path,
+    process::ExitCode,
+};
 
 use clap::Parser;
+use serde::Serialize;
 
 // Can Rust PLEASE add a way to bundle `mod` statements
 mod args;
+mod backends;
 mod constants;
+mod output;
 mod utils;
 mod wpserver;
 
 use args::*;
-use constants::*;
+use backends::{BackendKind, BackendOptions};
 use fern::Dispatch;
-use utils::socket_utils;
-use wpserver::server::{WallpaperOptions, WallpaperServer};
+use utils::{
+    file_utils::ScanOptions,
+    socket_utils::{self, ClientError, Packet, Response},
+};
+use wpserver::{
+    messages::*,
+    server::{SelectionMode, WallpaperOptions, WallpaperServer},
+};
 
 // TODO: See if there's a better way to return out of main... I don't like unnecessarily using Box<dyn Error>.
 // Also for some reason, anyhow::Result<()> won't work with nix::unistd::daemon()'s Error variant
-fn main() -> Result<(), Box<dyn Error>> {
+//
+// Client commands report how the request went through the exit code, see `constants.rs`
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let args = Args::parse();
+    // Made absolute right away, the server changes directory to `/` when it daemonizes
+    let socket = socket_utils::resolve_socket_path(args.socket.clone())?;
 
     // Parse subcommand
     use Opt::*;
@@ -29,7 +47,22 @@ fn main() -> Result<(), Box<dyn Error>> {
             fg: run_here,
             log,
             recursive,
+            extensions,
+            poll,
             random,
+            selection,
+            include,
+            exclude,
+            sort,
+            backend,
+            mode,
+            transition_type,
+            transition_duration,
+            transition_fps,
+            command,
+            dry_run,
+            record,
+            outputs,
         } => {
             let logger = setup_logger();
             if let Some(log_file) = log {
@@ -42,8 +75,32 @@ fn main() -> Result<(), Box<dyn Error>> {
             let options = WallpaperOptions {
                 directory,
                 duration,
-                recursive,
-                random,
+                scan: ScanOptions {
+                    recursive,
+                    extensions: extensions.iter().map(|e| e.to_lowercase()).collect(),
+                    include,
+                    exclude,
+                    sort,
+                },
+                poll,
+                selection: match random {
+                    true => SelectionMode::Shuffle,
+                    false => selection,
+                },
+                backend: match dry_run {
+                    true => BackendKind::DryRun,
+                    false => backend,
+                },
+                backend_options: BackendOptions {
+                    mode,
+                    transition_type,
+                    transition_duration,
+                    transition_fps,
+                    command,
+                    record,
+                },
+                outputs,
+                socket,
             };
 
             let mut server = match WallpaperServer::new(options) {
@@ -77,47 +134,128 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
+        // Doesn't get a single reply, events are printed until the server goes away
+        Watch => return Ok(watch(&socket, args.json)),
         command => {
             // Parse the command and send the appropriate request
             let request_result = match command {
-                Setwp { path } => socket_utils::send_request("SETWP", &path, FILE_SOCKET),
-                Getwp => socket_utils::send_request("GETWP", "", FILE_SOCKET),
-                Next => socket_utils::send_request("NEXT", "", FILE_SOCKET),
-                Getdir => socket_utils::send_request("GETDIR", "", FILE_SOCKET),
+                Setwp { path, output } => {
+                    let path = absolute(path);
+                    send("SETWP", &SetWpRequest { path, output }, &socket)
+                }
+                Getwp { output } => send("GETWP", &OutputRequest { output }, &socket),
+                Next { output } => send("NEXT", &OutputRequest { output }, &socket),
+                Pause { duration } => send(
+                    "PAUSE",
+                    &PauseRequest {
+                        duration: duration
+                            .map(|duration| humantime::format_duration(duration).to_string()),
+                    },
+                    &socket,
+                ),
+                Resume => send("RESUME", &EmptyRequest {}, &socket),
+                Toggle => send("TOGGLE", &EmptyRequest {}, &socket),
+                SetDuration { duration } => send(
+                    "SETDURATION",
+                    &SetDurationRequest {
+                        duration: humantime::format_duration(duration).to_string(),
+                    },
+                    &socket,
+                ),
+                GetDuration => send("GETDURATION", &EmptyRequest {}, &socket),
+                Prev { output } => send("PREV", &OutputRequest { output }, &socket),
+                History { count, output } => {
+                    send("HISTORY", &HistoryRequest { count, output }, &socket)
+                }
+                Status => send("STATUS", &EmptyRequest {}, &socket),
+                Getdir => send("GETDIR", &EmptyRequest {}, &socket),
                 Setdir {
                     directory,
                     recursive,
                     random,
+                    selection,
+                    include,
+                    exclude,
+                    sort,
                 } => {
-                    let recursive = match recursive {
-                        true => "true",
-                        false => "",
+                    let request = SetDirRequest {
+                        path: absolute(directory),
+                        recursive,
+                        selection: match random {
+                            true => SelectionMode::Shuffle,
+                            false => selection,
+                        },
+                        include,
+                        exclude,
+                        sort,
                     };
-                    let random = match random {
-                        true => "true",
-                        false => "",
-                    };
-                    socket_utils::send_request(
-                        "SETDIR",
-                        &format!("{}\n{}\n{}", recursive, random, &directory),
-                        FILE_SOCKET,
-                    )
+                    send("SETDIR", &request, &socket)
                 }
-                Ping => socket_utils::send_request("PING", "", FILE_SOCKET),
-                Kill => socket_utils::send_request("KILL", "", FILE_SOCKET),
+                Ping => send("PING", &EmptyRequest {}, &socket),
+                Kill => send("KILL", &EmptyRequest {}, &socket),
                 _ => unreachable!(), // Won't be reached since we already matched all possible subcommands
             };
 
-            // Get the response/error and print it to the screen
-            match request_result {
-                Ok(response) => println!("{response}"),
-                Err(e) => {
-                    eprintln!("Ran into error while sending request: {e}\nIs the server running?")
-                }
+            // Print the response/error. Scripts can tell what went wrong by the exit code
+            return Ok(output::print_reply(request_result, args.json));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Sends `command` along with its typed request
+fn send(command: &str, request: &impl Serialize, socket: &Path) -> Result<Response, ClientError> {
+    socket_utils::send_packet(Packet::new().command(command).json(request), socket)
+}
+
+/// Makes `path` absolute, since the server's working directory is most likely not ours.
+/// Anything that can't be made absolute is left for the server to reject
+fn absolute(path: String) -> String {
+    match std::path::absolute(&path).map(|path| path.into_os_string().into_string()) {
+        Ok(Ok(absolute)) => absolute,
+        _ => path,
+    }
+}
+
+/// Prints every event the server sends until it closes the connection
+fn watch(socket: &Path, json: bool) -> ExitCode {
+    let request = Packet::new().command("SUBSCRIBE").json(&EmptyRequest {});
+    let (head, mut reader) = match socket_utils::open_stream(request, socket) {
+        Ok(opened) => opened,
+        Err(e) => return output::print_reply(Err(e), json),
+    };
+
+    // Anything but a 2xx is a regular reply
+    match head.status() {
+        Some(200..=299) => {}
+        Some(status) => {
+            let mut body = String::new();
+            let _ = reader.read_to_string(&mut body);
+            let response = Response {
+                status,
+                body,
+                json: head
+                    .get_header("Content-Type")
+                    .is_some_and(|value| value.contains("application/json")),
+            };
+            return output::print_reply(Ok(response), json);
+        }
+        None => {
+            let e = ClientError::Protocol("reply is missing a status code".to_string());
+            return output::print_reply(Err(e), json);
+        }
+    }
+
+    for line in reader.lines() {
+        match line {
+            Ok(line) => output::print_event(&line, json),
+            Err(e) => {
+                let e = ClientError::Protocol(format!("could not read event: {e}"));
+                return output::print_reply(Err(e), json);
             }
         }
     }
-    Ok(())
+    ExitCode::SUCCESS
 }
 
 /// Sets up the bare bones logger. The caller (`main`) can then choose to chain a log file or not
@@ -0,0 +1,115 @@
+use std::{
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    constants::*,
+    utils::socket_utils::{ClientError, Response},
+    wpserver::{events::Event, messages::Reply},
+};
+
+/// What `--json` prints for every command
+#[derive(Serialize)]
+struct JsonReply {
+    /// The server's status code, or `None` if it couldn't be reached
+    status: Option<u16>,
+    message: String,
+    data: Value,
+}
+
+/// Prints the server's reply (or the error that kept us from getting one), either for humans or as
+/// a JSON object. Errors go to stderr unless printing JSON. Returns the exit code for the command
+pub fn print_reply(result: Result<Response, ClientError>, json: bool) -> ExitCode {
+    write_reply(result, json, &mut io::stdout(), &mut io::stderr())
+}
+
+/// `print_reply()`, writing to `out` and `err` instead of stdout and stderr
+pub(crate) fn write_reply(
+    result: Result<Response, ClientError>,
+    json: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> ExitCode {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            let message = format!("Ran into error while sending request: {e}");
+            let code = match e {
+                ClientError::Connection(_) => EXIT_CONNECTION_ERROR,
+                ClientError::Protocol(_) => EXIT_PROTOCOL_ERROR,
+            };
+            match (json, code) {
+                (true, _) => write_json(
+                    out,
+                    err,
+                    JsonReply {
+                        status: None,
+                        message,
+                        data: Value::Null,
+                    },
+                ),
+                (false, EXIT_CONNECTION_ERROR) => {
+                    let _ = writeln!(err, "{message}\nIs the server running?");
+                }
+                (false, _) => {
+                    let _ = writeln!(err, "{message}");
+                }
+            }
+            return ExitCode::from(code);
+        }
+    };
+
+    // Servers from before typed replies only ever send plain text
+    let reply = match response.json {
+        true => serde_json::from_str::<Reply>(&response.body).ok(),
+        false => None,
+    }
+    .unwrap_or_else(|| Reply::new(response.status, response.body.clone()));
+
+    let success = response.is_success();
+    match (json, success) {
+        (true, _) => write_json(
+            out,
+            err,
+            JsonReply {
+                status: Some(response.status),
+                message: reply.message,
+                data: match success {
+                    true => reply.data,
+                    false => Value::Null,
+                },
+            },
+        ),
+        (false, true) => {
+            let _ = writeln!(out, "{}", reply.message);
+        }
+        (false, false) => {
+            let _ = writeln!(err, "{}", reply.message);
+        }
+    }
+
+    match response.status {
+        200..=299 => ExitCode::SUCCESS,
+        400..=499 => ExitCode::from(EXIT_REJECTED),
+        _ => ExitCode::from(EXIT_SERVER_ERROR),
+    }
+}
+
+/// Prints a line of `wonderwall watch`. Lines that aren't an event we know of are printed as they are
+pub fn print_event(line: &str, json: bool) {
+    match (json, serde_json::from_str::<Event>(line)) {
+        (false, Ok(event)) => println!("{event}"),
+        _ => println!("{line}"),
+    }
+}
+
+fn write_json(out: &mut impl Write, err: &mut impl Write, reply: JsonReply) {
+    let _ = match serde_json::to_string(&reply) {
+        Ok(reply) => writeln!(out, "{reply}"),
+        Err(e) => writeln!(err, "Could not encode reply as JSON: {e}"),
+    };
+}
@@ -0,0 +1,227 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use nix::sys::statfs::{self, FsType};
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+use super::file_utils::{self, ScanOptions, SortMode, IGNORE_FILE};
+
+/// Filesystems where inotify only sees local changes, so watching them would silently miss files
+/// added from other machines. `nix` doesn't have constants for all of them
+const NETWORK_FILESYSTEMS: &[FsType] = &[
+    statfs::NFS_SUPER_MAGIC,
+    statfs::SMB_SUPER_MAGIC,
+    statfs::FUSE_SUPER_MAGIC,
+    FsType(0xff534d42_u32 as _), // CIFS
+    FsType(0xfe534d42_u32 as _), // SMB2
+    FsType(0x00c36400_u32 as _), // Ceph
+];
+
+/// Every wallpaper in a directory.
+///
+/// The directory is only scanned once, after that a filesystem watcher keeps the list up to date as
+/// files are created, deleted and renamed. Directories that can't be watched (or network mounts) are
+/// rescanned every cycle instead.
+pub struct WallpaperIndex {
+    /// Canonical path of the directory
+    pub directory: PathBuf,
+    pub scan: ScanOptions,
+    pub files: Vec<String>,
+    /// Set when changes aren't being watched, so the index has to be rescanned to notice them
+    pub polling: bool,
+    /// Never watch, always poll
+    pub force_poll: bool,
+    watcher: Option<RecommendedWatcher>,
+}
+
+pub type SharedIndex = Arc<Mutex<WallpaperIndex>>;
+
+impl WallpaperIndex {
+    /// Scans `directory`. Call `watch()` afterwards to keep the index up to date
+    pub fn new(directory: &str, scan: ScanOptions, force_poll: bool) -> io::Result<Self> {
+        let directory = PathBuf::from(directory).canonicalize()?;
+        let files = file_utils::get_directory_files(&directory, &scan)?;
+
+        Ok(Self {
+            directory,
+            scan,
+            files,
+            polling: true,
+            force_poll,
+            watcher: None,
+        })
+    }
+
+    /// Starts watching the index's directory for changes, replacing any previous watcher.
+    /// Falls back to polling if the directory can't (or shouldn't) be watched
+    pub fn watch(index: &SharedIndex) {
+        let mut guard = index.lock().unwrap();
+
+        // Dropping the old watcher stops it, but it might be waiting on the lock we're holding
+        let old_watcher = guard.watcher.take();
+        guard.polling = true;
+
+        match guard.start_watcher(index) {
+            Ok(Some(watcher)) => {
+                log::info!("Watching {:?} for changes", guard.directory);
+                guard.watcher = Some(watcher);
+                guard.polling = false;
+            }
+            Ok(None) => log::info!("Rescanning {:?} every cycle", guard.directory),
+            Err(e) => log::warn!(
+                "Could not watch {:?}, rescanning it every cycle instead: {e}",
+                guard.directory
+            ),
+        }
+
+        drop(guard);
+        drop(old_watcher);
+    }
+
+    /// Creates a watcher for the directory, or `None` if it should be polled instead
+    fn start_watcher(&self, index: &SharedIndex) -> notify::Result<Option<RecommendedWatcher>> {
+        if self.force_poll {
+            return Ok(None);
+        }
+
+        let fs_type = statfs::statfs(&self.directory)
+            .map_err(|e| notify::Error::io(e.into()))?
+            .filesystem_type();
+        if NETWORK_FILESYSTEMS.contains(&fs_type) {
+            log::warn!("{:?} is a network filesystem", self.directory);
+            return Ok(None);
+        }
+
+        // The watcher lives inside the index, so it only gets a weak handle back to it
+        let weak_index = Arc::downgrade(index);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Some(index) = weak_index.upgrade() else {
+                return;
+            };
+            match event {
+                Ok(event) => WallpaperIndex::handle_event(&index, event),
+                Err(e) => log::warn!("Filesystem watcher error: {e}"),
+            }
+        })?;
+
+        let mode = match self.scan.recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        watcher.watch(&self.directory, mode)?;
+        Ok(Some(watcher))
+    }
+
+    /// Rebuilds the index from scratch. The lock isn't held while scanning
+    pub fn rescan(index: &SharedIndex) -> io::Result<()> {
+        let (directory, scan) = {
+            let index = index.lock().unwrap();
+            (index.directory.clone(), index.scan.clone())
+        };
+
+        let files = file_utils::get_directory_files(&directory, &scan)?;
+
+        let mut index = index.lock().unwrap();
+        // The directory might have been changed while we were scanning
+        if index.directory == directory {
+            index.files = files;
+        }
+        Ok(())
+    }
+
+    /// Updates the index for a single filesystem event
+    pub(crate) fn handle_event(index: &SharedIndex, event: Event) {
+        // The kernel's event queue overflowed (e.g. a big collection was copied in), so some
+        // changes were lost and only a full rescan can tell what they were
+        if event.need_rescan() {
+            log::info!("Missed some filesystem events, rescanning");
+            if let Err(e) = WallpaperIndex::rescan(index) {
+                log::warn!("Rescan failed: {e}");
+            }
+            return;
+        }
+
+        // Reads don't change anything, but a file being closed after writing might have finished downloading
+        let structural = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => false,
+            EventKind::Access(_) => return,
+            EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(_)) => true,
+            _ => false,
+        };
+
+        for path in event.paths {
+            let needs_rescan = {
+                let mut index = index.lock().unwrap();
+                index.update_path(&path, structural)
+            };
+
+            if needs_rescan {
+                log::info!("{path:?} changed, rescanning");
+                if let Err(e) = WallpaperIndex::rescan(index) {
+                    log::warn!("Rescan failed: {e}");
+                }
+            }
+        }
+    }
+
+    /// Adds, keeps or removes a single path. Returns `true` when the change is too big to handle
+    /// incrementally (a directory or an ignore file changed) and the whole index should be rescanned
+    fn update_path(&mut self, path: &Path, structural: bool) -> bool {
+        // Events from a watcher that was just replaced
+        if !path.starts_with(&self.directory) {
+            return false;
+        }
+
+        if path.file_name().is_some_and(|name| name == IGNORE_FILE) {
+            return true;
+        }
+
+        if path.is_dir() {
+            return structural;
+        }
+
+        let Some(path_str) = path.to_str() else {
+            return false;
+        };
+
+        if path.exists() {
+            let is_wallpaper = file_utils::is_wallpaper(&self.directory, path, &self.scan);
+            let position = self.files.iter().position(|file| file == path_str);
+            match (is_wallpaper, position) {
+                (true, None) => {
+                    log::debug!("Adding {path_str} to the index");
+                    let position =
+                        file_utils::sorted_position(&self.files, path_str, self.scan.sort);
+                    self.files.insert(position, path_str.to_string());
+                }
+                (false, Some(position)) => {
+                    log::debug!("Removing {path_str} from the index");
+                    self.files.remove(position);
+                }
+                // The file was written to, which moves it when sorting by date or size
+                (true, Some(position))
+                    if !matches!(self.scan.sort, SortMode::Natural | SortMode::Path) =>
+                {
+                    let file = self.files.remove(position);
+                    let position = file_utils::sorted_position(&self.files, &file, self.scan.sort);
+                    self.files.insert(position, file);
+                }
+                _ => {}
+            }
+        } else {
+            // Whatever was there is gone. If it was a directory, so is everything inside it
+            let dir_prefix = format!("{path_str}/");
+            self.files
+                .retain(|file| file != path_str && !file.starts_with(&dir_prefix));
+        }
+        false
+    }
+}
@@ -1,95 +1,398 @@
 use std::{
-    error::Error,
-    fmt::Display,
-    io::{self},
-    path::PathBuf,
+    cmp::Ordering,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-#[derive(Debug)]
-pub struct HyprpaperError(pub String);
+use clap::ValueEnum;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
 
-impl Display for HyprpaperError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+/// Name of the gitignore-style file that hides files from the scan of the directory it's in (and below)
+pub const IGNORE_FILE: &str = ".wonderwallignore";
+
+/// Extensions that are considered images unless the user says otherwise
+pub const DEFAULT_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "gif", "bmp", "jxl"];
+
+/// Order wallpapers are shown in when not in random mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// By path, with numbers compared by value (`img2` before `img10`) and ignoring case
+    #[default]
+    Natural,
+    /// By path, byte by byte
+    Path,
+    /// Most recently modified first
+    Newest,
+    /// Least recently modified first
+    Oldest,
+    /// Smallest files first
+    Size,
+}
+
+/// Options controlling which files a directory scan picks up
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Also scan every subdirectory
+    pub recursive: bool,
+    /// Lowercase extensions (without the dot) of files that are considered images
+    pub extensions: Vec<String>,
+    /// If not empty, only files matching one of these globs are picked up
+    pub include: Vec<String>,
+    /// Files and directories matching any of these globs are skipped
+    pub exclude: Vec<String>,
+    /// Order of the scanned files
+    pub sort: SortMode,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            extensions: DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            include: vec![],
+            exclude: vec![],
+            sort: SortMode::default(),
+        }
     }
 }
-impl Error for HyprpaperError {}
 
-pub fn hyprpaper_update(path: &str) -> Result<(), Box<dyn Error>> {
-    let preload = format!("hyprctl hyprpaper preload {}", path);
+/// `--include`/`--exclude` globs, compiled once per scan
+struct Filters {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
 
-    let stdout = exec_command(&preload)?;
-    if stdout != "ok\n" {
-        return Err(Box::from(HyprpaperError(stdout)));
+impl Filters {
+    fn new(options: &ScanOptions) -> io::Result<Self> {
+        let build = |patterns: &[String]| -> io::Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                let glob = Glob::new(pattern)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                builder.add(glob);
+            }
+            builder
+                .build()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        };
+
+        Ok(Self {
+            include: match options.include.is_empty() {
+                true => None,
+                false => Some(build(&options.include)?),
+            },
+            exclude: build(&options.exclude)?,
+        })
     }
+}
 
-    let load = format!("hyprctl hyprpaper wallpaper \', {}\'", path);
-    let stdout = exec_command(&load)?;
-    if stdout != "ok\n" {
-        return Err(Box::from(HyprpaperError(stdout)));
+/// Checks a path against the `.wonderwallignore` files found on the way down to it.
+/// Deeper files take precedence, so they can un-ignore (`!pattern`) things a parent ignored
+fn is_ignored(path: &Path, is_dir: bool, ignores: &[Gitignore]) -> bool {
+    for gitignore in ignores.iter().rev() {
+        let matched = gitignore.matched(path, is_dir);
+        if matched.is_ignore() {
+            return true;
+        }
+        if matched.is_whitelist() {
+            return false;
+        }
     }
+    false
+}
+
+/// Extensions we know the magic bytes of (`has_image_magic()` has a signature for every default
+/// extension). Files with any other (allowed) extension are taken at face value
+const SNIFFABLE_EXTENSIONS: &[&str] = DEFAULT_EXTENSIONS;
+
+/// Checks the first few bytes of a file against the signatures of the image formats we know
+fn has_image_magic(header: &[u8]) -> bool {
+    const JXL_CONTAINER: &[u8] = &[
+        0x00, 0x00, 0x00, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+    ];
 
-    let unload_unused = "hyprctl hyprpaper unload unused";
-    let stdout = exec_command(unload_unused)?;
-    if stdout != "ok\n" {
-        return Err(Box::new(HyprpaperError(stdout)));
+    header.starts_with(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])
+        || header.starts_with(&[0xff, 0xd8, 0xff])
+        || header.starts_with(b"GIF87a")
+        || header.starts_with(b"GIF89a")
+        || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP"))
+        || header.starts_with(b"BM")
+        || header.starts_with(&[0xff, 0x0a])
+        || header.starts_with(JXL_CONTAINER)
+}
+
+/// Decides whether a file is an image we should hand to the backend, based on its extension and its contents.
+///
+/// This keeps things like `.DS_Store`, `Thumbs.db` and half-downloaded `.part` files out of the rotation
+pub fn is_image(path: &Path, options: &ScanOptions) -> bool {
+    let Some(extension) = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+    else {
+        log::debug!("Skipping {path:?}: no extension");
+        return false;
+    };
+
+    if !options.extensions.contains(&extension) {
+        log::debug!("Skipping {path:?}: `{extension}` is not an allowed extension");
+        return false;
     }
-    Ok(())
+
+    let mut header = Vec::with_capacity(12);
+    if let Err(e) = File::open(path).and_then(|f| f.take(12).read_to_end(&mut header)) {
+        log::debug!("Skipping {path:?}: could not read file ({e})");
+        return false;
+    }
+
+    let is_image = match SNIFFABLE_EXTENSIONS.contains(&extension.as_str()) {
+        true => has_image_magic(&header),
+        false => !header.is_empty(),
+    };
+    if !is_image {
+        log::debug!("Skipping {path:?}: contents don't look like an image");
+    }
+    is_image
 }
 
-pub fn exec_command(command: &str) -> io::Result<String> {
-    log::info!("Executing command: `{}`", &command);
-    let output = std::process::Command::new("bash")
-        .arg("-c")
-        .arg(command)
-        .output()?;
-
-    Ok(output.stdout.iter().map(|&c| char::from(c)).collect())
-}
-
-//pub fn get_directory_files(path: &str) -> io::Result<Vec<String>> {
-//    let path = PathBuf::from(path).canonicalize()?;
-//
-//    let mut contents: Vec<_> = std::fs::read_dir(&path)?
-//        .filter_map(|e| e.ok())
-//        .filter_map(|e| {
-//            let file_type = e.file_type().ok()?;
-//            let is_file = file_type.is_file();
-//            let path_str = e.path().to_str().map(|s| s.to_string());
-//
-//            // Log each entry's attributes if on debug mode
-//            #[cfg(debug_assertions)]
-//            {
-//                log::debug!(
-//                    "Found entry: {:?}, is_file: {}, path: {:?}",
-//                    e.file_name(),
-//                    is_file,
-//                    path_str
-//                );
-//            }
-//            is_file.then_some(path_str)?
-//        })
-//        .collect();
-//
-//    contents.sort();
-//
-//    Ok(contents)
-//}
-
-pub fn get_directory_files(path: &PathBuf, recursive: bool) -> io::Result<Vec<String>> {
-    let path = PathBuf::from(path).canonicalize()?;
+pub fn get_directory_files(path: &PathBuf, options: &ScanOptions) -> io::Result<Vec<String>> {
+    let root = PathBuf::from(path).canonicalize()?;
+    let filters = Filters::new(options)?;
     let mut images: Vec<String> = vec![];
 
-    for entry in std::fs::read_dir(&path)?.flatten() {
+    scan_directory(&root, &root, options, &filters, &mut vec![], &mut images)?;
+    sort_files(&mut images, options.sort);
+    Ok(images)
+}
+
+/// Sorts `files` in place. Ties (and files whose metadata can't be read) fall back to natural order,
+/// so the result doesn't depend on the order the filesystem listed them in
+pub fn sort_files(files: &mut [String], mode: SortMode) {
+    match mode {
+        SortMode::Natural => files.sort_by(|a, b| natural_cmp(a, b)),
+        SortMode::Path => files.sort(),
+        SortMode::Newest | SortMode::Oldest | SortMode::Size => {
+            files.sort_by_cached_key(|file| metadata_key(file, mode));
+        }
+    }
+}
+
+/// Where `file` belongs in `files`, which are already sorted by `mode`. Only the files it gets
+/// compared against have their metadata read, so this is much cheaper than sorting again
+pub fn sorted_position(files: &[String], file: &str, mode: SortMode) -> usize {
+    match mode {
+        SortMode::Natural => files.partition_point(|probe| natural_cmp(probe, file).is_lt()),
+        SortMode::Path => files.partition_point(|probe| probe.as_str() < file),
+        SortMode::Newest | SortMode::Oldest | SortMode::Size => {
+            let key = metadata_key(file, mode);
+            files.partition_point(|probe| metadata_key(probe, mode) < key)
+        }
+    }
+}
+
+/// Sort key for the modes that go by metadata
+fn metadata_key(file: &str, mode: SortMode) -> (u128, NaturalKey) {
+    let metadata = std::fs::metadata(file).ok();
+    let key = match mode {
+        SortMode::Size => metadata.map(|m| m.len() as u128),
+        _ => metadata
+            .and_then(|m| m.modified().ok())
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos()),
+    };
+    let key = match mode {
+        SortMode::Newest => key.map(|key| u128::MAX - key),
+        _ => key,
+    };
+    (key.unwrap_or(u128::MAX), NaturalKey(file.to_string()))
+}
+
+/// Wrapper so natural order can be used as (part of) a sort key
+#[derive(PartialEq, Eq)]
+struct NaturalKey(String);
+
+impl PartialOrd for NaturalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NaturalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        natural_cmp(&self.0, &other.0)
+    }
+}
+
+/// Compares strings the way a human would: case insensitively, and with runs of digits compared by value
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => break,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                        digits.push(c);
+                    }
+                    digits
+                };
+                let x = take_number(&mut a_chars);
+                let y = take_number(&mut b_chars);
+
+                // Compare by value without parsing, so huge numbers can't overflow
+                let x_trimmed = x.trim_start_matches('0');
+                let y_trimmed = y.trim_start_matches('0');
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+
+    // Only differ in case or leading zeroes, so fall back to a plain comparison to keep the order total
+    a.cmp(b)
+}
+
+/// Reads the `.wonderwallignore` in `dir`, if there is one
+fn load_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let ignore_file = dir.join(IGNORE_FILE);
+    if !ignore_file.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(&ignore_file) {
+        log::warn!("Problem reading {ignore_file:?}: {e}");
+    }
+    builder
+        .build()
+        .inspect_err(|e| log::warn!("Ignoring {ignore_file:?}: {e}"))
+        .ok()
+}
+
+/// Checks a file against the globs and ignore files. `relative` is its path relative to the scan's root
+fn passes_filters(path: &Path, relative: &Path, filters: &Filters, ignores: &[Gitignore]) -> bool {
+    let passes = !filters.exclude.is_match(relative)
+        && filters
+            .include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative))
+        && !is_ignored(path, false, ignores);
+
+    if !passes {
+        log::debug!("Skipping {path:?}: filtered out");
+    }
+    passes
+}
+
+/// Appends the images in `path` (and its subdirectories if recursive) to `images`.
+///
+/// Globs are matched against paths relative to `root`, the directory the scan started from.
+fn scan_directory(
+    root: &Path,
+    path: &Path,
+    options: &ScanOptions,
+    filters: &Filters,
+    ignores: &mut Vec<Gitignore>,
+    images: &mut Vec<String>,
+) -> io::Result<()> {
+    let ignore_file = load_ignore_file(path);
+    let pushed_ignore = ignore_file.is_some();
+    ignores.extend(ignore_file);
+
+    for entry in std::fs::read_dir(path)?.flatten() {
+        let entry_path = entry.path();
+        let relative = entry_path.strip_prefix(root).unwrap_or(&entry_path);
+
         if let Ok(file_type) = entry.file_type() {
             if file_type.is_file() {
-                if let Some(path) = entry.path().to_str() {
+                if !passes_filters(&entry_path, relative, filters, ignores)
+                    || !is_image(&entry_path, options)
+                {
+                    continue;
+                }
+                if let Some(path) = entry_path.to_str() {
                     images.push(path.to_string())
                 }
-            } else if file_type.is_dir() && recursive {
-                images.append(&mut get_directory_files(&entry.path(), true)?);
+            } else if file_type.is_dir() && options.recursive {
+                if filters.exclude.is_match(relative) || is_ignored(&entry_path, true, ignores) {
+                    log::debug!("Skipping directory {entry_path:?}: filtered out");
+                    continue;
+                }
+                scan_directory(root, &entry_path, options, filters, ignores, images)?;
             }
         }
     }
-    Ok(images)
+
+    // This directory's ignore file doesn't apply to its siblings
+    if pushed_ignore {
+        ignores.pop();
+    }
+    Ok(())
+}
+
+/// Decides whether a single file would be picked up by `get_directory_files(root, options)`,
+/// without scanning anything else. `root` has to be canonical.
+pub fn is_wallpaper(root: &Path, path: &Path, options: &ScanOptions) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let Some(relative_parent) = relative.parent() else {
+        return false;
+    };
+
+    // Non recursive scans only ever look at the root itself
+    if !options.recursive && relative_parent != Path::new("") {
+        return false;
+    }
+
+    let Ok(filters) = Filters::new(options) else {
+        return false;
+    };
+
+    // Walk down to the file the same way a scan would, so filtered out directories and ignore files are respected
+    let mut ignores: Vec<Gitignore> = load_ignore_file(root).into_iter().collect();
+    let mut dir = root.to_path_buf();
+    for component in relative_parent.components() {
+        dir.push(component);
+        // Scans don't follow symlinks, so neither can this
+        if !dir
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            return false;
+        }
+        let dir_relative = dir.strip_prefix(root).unwrap_or(&dir);
+        if filters.exclude.is_match(dir_relative) || is_ignored(&dir, true, &ignores) {
+            return false;
+        }
+        ignores.extend(load_ignore_file(&dir));
+    }
+
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_file())
+        && passes_filters(path, relative, &filters, &ignores)
+        && is_image(path, options)
 }
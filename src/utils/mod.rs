@@ -1,2 +1,5 @@
+pub mod file_index;
 pub mod file_utils;
 pub mod socket_utils;
+pub mod time_utils;
+pub mod weights;
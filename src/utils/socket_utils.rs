@@ -1,12 +1,18 @@
 use std::{
     collections::HashMap,
     error::Error,
+    ffi::OsString,
     fmt::Display,
-    io::{self, BufRead, Write},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
     os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
 use regex::Regex;
+use serde::Serialize;
+
+use crate::constants::*;
 
 #[derive(Debug)]
 pub struct PacketError<'a>(pub &'a str);
@@ -18,8 +24,62 @@ impl Display for PacketError<'_> {
 }
 impl Error for PacketError<'_> {}
 
+/// Ways reading a packet off a socket can fail
+#[derive(Debug)]
+pub enum ReadError {
+    /// The connection failed or timed out
+    Io(io::Error),
+    /// The headers or body were over `MAX_HEADER_SIZE`/`MAX_BODY_SIZE`
+    TooLarge(&'static str),
+    /// Whatever came in wasn't a packet
+    BadFormat(&'static str),
+    /// The packet uses a part of HTTP we don't handle
+    Unsupported(&'static str),
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => e.fmt(f),
+            ReadError::TooLarge(msg) | ReadError::BadFormat(msg) | ReadError::Unsupported(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// REST routes for the commands, as `(method, path, command)`. The command is what older clients
+/// send in the `WallpaperControl` header
+pub const ROUTES: &[(&str, &str, &str)] = &[
+    ("GET", "/wallpaper", "GETWP"),
+    ("PUT", "/wallpaper", "SETWP"),
+    ("POST", "/next", "NEXT"),
+    ("POST", "/prev", "PREV"),
+    ("GET", "/history", "HISTORY"),
+    ("POST", "/pause", "PAUSE"),
+    ("POST", "/resume", "RESUME"),
+    ("POST", "/toggle", "TOGGLE"),
+    ("GET", "/duration", "GETDURATION"),
+    ("PUT", "/duration", "SETDURATION"),
+    ("GET", "/status", "STATUS"),
+    ("GET", "/directory", "GETDIR"),
+    ("PUT", "/directory", "SETDIR"),
+    ("GET", "/ping", "PING"),
+    ("POST", "/kill", "KILL"),
+    ("GET", "/events", "SUBSCRIBE"),
+];
+
 #[derive(Debug)]
 pub struct Packet {
+    /// Request line (e.g. `GET /wallpaper HTTP/1.1`) or status line (e.g. `HTTP/1.1 200 OK`)
     pub method: String,
     pub headers: HashMap<String, String>,
     pub body: String,
@@ -28,18 +88,43 @@ pub struct Packet {
 impl Packet {
     pub fn new() -> Self {
         Self {
-            method: String::from("POST /"),
+            method: String::from("POST / HTTP/1.1"),
             headers: HashMap::new(),
             body: String::from(""),
         }
     }
 
-    /// Sets method
+    /// Makes this a response with the given status code, e.g. `"200"`
     pub fn method(mut self, method: &str) -> Self {
-        self.method = format!("HTTP/1.1 {}", method.trim());
+        let reason = match method.trim() {
+            "200" => "OK",
+            "400" => "Bad Request",
+            "404" => "Not Found",
+            "405" => "Method Not Allowed",
+            "408" => "Request Timeout",
+            "413" => "Content Too Large",
+            "500" => "Internal Server Error",
+            "501" => "Not Implemented",
+            "503" => "Service Unavailable",
+            _ => "",
+        };
+        self.method = format!("HTTP/1.1 {} {reason}", method.trim())
+            .trim_end()
+            .to_string();
         self
     }
 
+    /// Makes this a request for `command` (e.g. `NEXT`).
+    ///
+    /// It's sent to the command's route, but also carries the old header so that a server started
+    /// before the routes existed still understands it
+    pub fn command(mut self, command: &str) -> Self {
+        if let Some((method, path, _)) = ROUTES.iter().find(|(.., name)| *name == command) {
+            self.method = format!("{method} {path} HTTP/1.1");
+        }
+        self.header("WallpaperControl", command)
+    }
+
     /// Sets header
     pub fn header(mut self, key: &str, value: &str) -> Self {
         self.headers
@@ -53,11 +138,43 @@ impl Packet {
         self
     }
 
+    /// Sets the body to `message` as JSON, and asks for a JSON reply
+    pub fn json(self, message: &impl Serialize) -> Self {
+        // Plain structs can't fail to serialize, but just in case the server will complain about the empty body
+        let body = serde_json::to_string(message).unwrap_or_default();
+        self.header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .body(&body)
+    }
+
+    /// Value of a header, ignoring the case of its name like HTTP does
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Method and target of a request, e.g. `("GET", "/wallpaper?output=DP-1")`
+    pub fn target(&self) -> Option<(&str, &str)> {
+        let mut parts = self.method.split_whitespace();
+        Some((parts.next()?, parts.next()?))
+    }
+
+    /// Status code of a response, e.g. `200` for `HTTP/1.1 200 OK`
+    pub fn status(&self) -> Option<u16> {
+        self.method
+            .split_whitespace()
+            .find_map(|part| part.parse::<u16>().ok())
+    }
+
     /// Deserializes the packet from raw bytes
     pub fn from_bytes<'a>(buffer: Vec<u8>) -> Result<Self, PacketError<'a>> {
-        let re = Regex::new(r#"^([^\r\n]+)\r\n((.+: .+\r\n)*)\r\n([\s\S]*)"#).unwrap();
+        let re = Regex::new(r#"^([^\r\n]+)\r\n((.+:.*\r\n)*)\r\n([\s\S]*)"#).unwrap();
 
-        let buffer = String::from_utf8(buffer).unwrap();
+        let Ok(buffer) = String::from_utf8(buffer) else {
+            return Err(PacketError("Packet is not valid UTF-8"));
+        };
         let Some(caps) = re.captures(&buffer) else {
             return Err(PacketError("Bad format"));
         };
@@ -74,7 +191,7 @@ impl Packet {
                 if line.is_empty() {
                     continue;
                 }
-                let (key, value) = match line.split_once(": ") {
+                let (key, value) = match line.split_once(':') {
                     Some(kv) => kv,
                     None => return Err(PacketError("Bad header format")),
                 };
@@ -98,51 +215,326 @@ impl Packet {
         })
     }
 
-    /// Serializes the packet into bytes
+    /// Serializes the packet into bytes. `Content-Length` is always set from the body, and
+    /// `Connection` to `close`
     pub fn as_bytes(&self) -> Vec<u8> {
+        let mut output_buffer = self.head(Some(self.body.len()));
+        output_buffer.push_str(&self.body);
+        output_buffer.into()
+    }
+
+    /// Serializes only the start line and headers, for a body that's streamed until the
+    /// connection closes
+    pub fn head_bytes(&self) -> Vec<u8> {
+        self.head(None).into()
+    }
+
+    fn head(&self, content_length: Option<usize>) -> String {
         let mut output_buffer = String::new();
 
-        output_buffer.push_str(format!("{} HTTP/1.1\r\n", &self.method).as_str());
+        output_buffer.push_str(format!("{}\r\n", &self.method).as_str());
 
         for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case("Content-Length") || key.eq_ignore_ascii_case("Connection")
+            {
+                continue;
+            }
             output_buffer.push_str(format!("{key}: {value}\r\n").as_str());
         }
+        if let Some(length) = content_length {
+            output_buffer.push_str(format!("Content-Length: {length}\r\n").as_str());
+        }
+        // Every connection carries a single request and its reply
+        output_buffer.push_str("Connection: close\r\n");
         output_buffer.push_str("\r\n");
+        output_buffer
+    }
+}
 
-        output_buffer.push_str(&self.body);
+/// The server's reply to a request
+#[derive(Debug)]
+pub struct Response {
+    /// HTTP style status code, e.g. `200` or `400`
+    pub status: u16,
+    pub body: String,
+    /// Whether the body is JSON, going by its `Content-Type`
+    pub json: bool,
+}
 
-        output_buffer.into()
+impl Response {
+    /// Whether the server accepted the request (a 2xx status)
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
     }
 }
 
-pub fn send_request(command: &str, body: &str, address: &str) -> Result<String, Box<dyn Error>> {
-    let mut stream = UnixStream::connect(address)?;
+/// Ways sending a request can fail before the server gets to reply
+#[derive(Debug)]
+pub enum ClientError {
+    /// Couldn't connect to the socket, most likely because the server isn't running
+    Connection(io::Error),
+    /// Connected, but the request couldn't be sent or the reply couldn't be understood
+    Protocol(String),
+}
 
-    let request = Packet::new().header("WallpaperControl", command).body(body);
-    stream.write_all(&request.as_bytes())?;
-    stream.flush()?;
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Connection(e) => write!(f, "Could not connect to the server: {e}"),
+            ClientError::Protocol(msg) => write!(f, "Bad reply from the server: {msg}"),
+        }
+    }
+}
 
-    let response_bytes = extract_bytes_buffered(&mut stream)?;
-    let response = Packet::from_bytes(response_bytes)?;
+impl Error for ClientError {}
 
-    Ok(response.body)
+/// Decodes a query string key or value: `%XX` escapes become the byte they stand for and `+`
+/// becomes a space. Malformed escapes are left as they are
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
 }
 
-/// Given a buffer (in this case, File socketStream), use `BufReader` and `BufRead` trait
-/// to read the pending bytes in the stream
-///
-/// HOLY CRAP THANK YOU WHOEVER WROTE THIS, TOOK FOREVER TO WORK T_T
+/// Where the socket goes if `--socket` isn't given: `$XDG_RUNTIME_DIR/wonderwall.sock`, which only
+/// the current user can get to. Without a runtime dir, falls back to a per-user socket in the temp dir
+pub fn default_socket_path() -> PathBuf {
+    socket_path_in(std::env::var_os("XDG_RUNTIME_DIR"))
+}
+
+/// `default_socket_path()` for the given `XDG_RUNTIME_DIR`
+pub(crate) fn socket_path_in(runtime_dir: Option<OsString>) -> PathBuf {
+    match runtime_dir {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_FILE_NAME),
+        _ => std::env::temp_dir().join(format!("wonderwall-{}.sock", nix::unistd::getuid())),
+    }
+}
+
+/// The socket to use, given `--socket`. Relative paths are resolved against the current
+/// directory, so the server and its clients agree on them wherever they end up running
+pub fn resolve_socket_path(socket: Option<PathBuf>) -> io::Result<PathBuf> {
+    std::path::absolute(socket.unwrap_or_else(default_socket_path))
+}
+
+pub fn send_request(command: &str, body: &str, address: &Path) -> Result<Response, ClientError> {
+    let request = Packet::new().command(command).body(body);
+    send_packet(request, address)
+}
+
+/// Sends an already built request, for when `send_request` isn't flexible enough (e.g. extra headers)
+pub fn send_packet(request: Packet, address: &Path) -> Result<Response, ClientError> {
+    let mut stream = UnixStream::connect(address).map_err(ClientError::Connection)?;
+
+    stream
+        .set_read_timeout(Some(RESPONSE_TIMEOUT))
+        .and_then(|_| stream.write_all(&request.as_bytes()))
+        .and_then(|_| stream.flush())
+        .map_err(|e| ClientError::Protocol(format!("could not send request: {e}")))?;
+
+    let response = read_packet(&mut stream)
+        .map_err(|e| ClientError::Protocol(format!("could not read reply: {e}")))?;
+    let status = response
+        .status()
+        .ok_or_else(|| ClientError::Protocol("reply is missing a status code".to_string()))?;
+
+    let json = response
+        .get_header("Content-Type")
+        .is_some_and(|value| value.contains("application/json"));
+    Ok(Response {
+        status,
+        body: response.body,
+        json,
+    })
+}
+
+/// Sends a request whose reply is streamed, and reads the reply's head. The returned packet has no
+/// body, what follows the head is left in the reader
+pub fn open_stream(
+    request: Packet,
+    address: &Path,
+) -> Result<(Packet, BufReader<UnixStream>), ClientError> {
+    let mut stream = UnixStream::connect(address).map_err(ClientError::Connection)?;
+
+    stream
+        .write_all(&request.as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(|e| ClientError::Protocol(format!("could not send request: {e}")))?;
+
+    let read_error = |e: io::Error| ClientError::Protocol(format!("could not read reply: {e}"));
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    loop {
+        match reader.read_line(&mut head).map_err(read_error)? {
+            0 => return Err(read_error(ErrorKind::UnexpectedEof.into())),
+            _ if head.ends_with("\r\n\r\n") => break,
+            _ if head.len() > MAX_HEADER_SIZE => {
+                return Err(ClientError::Protocol("headers are too large".to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    let head = Packet::from_bytes(head.into_bytes())
+        .map_err(|e| ClientError::Protocol(format!("could not read reply: {e}")))?;
+    Ok((head, reader))
+}
+
+/// Reads from a socket, but gives up once `deadline` passes. Unlike a plain read timeout, a client
+/// that trickles in a byte at a time can't keep it going forever
+pub struct DeadlineReader<'a> {
+    pub stream: &'a UnixStream,
+    pub deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Reads a single packet off `stream`, waiting for the rest of it if it comes in pieces.
 ///
-/// https://github.com/thepacketgeek/rust-tcpstream-demo/blob/master/raw/src/lib.rs
-pub fn extract_bytes_buffered(mut buf: &mut impl io::Read) -> io::Result<Vec<u8>> {
-    let mut reader = io::BufReader::new(&mut buf);
+/// The body is `Content-Length` bytes long, or sent in chunks (`Transfer-Encoding: chunked`, which
+/// is what curl does for bodies from stdin). Packets with neither (from older clients) get whatever
+/// arrived along with the headers, which is how they used to be read
+pub fn read_packet(stream: &mut impl Read) -> Result<Packet, ReadError> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
 
-    // `fill_buf` will return a ref to the bytes pending (received by File socket)
-    // This is still a lower-level call, so we have to follow it up with a call to consume
-    let received: Vec<u8> = reader.fill_buf()?.to_vec();
+    // Read up to the blank line that ends the headers
+    let head_length = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buffer.len() > MAX_HEADER_SIZE {
+            return Err(ReadError::TooLarge("Headers are too large"));
+        }
+        match stream.read(&mut chunk)? {
+            0 => {
+                return Err(ReadError::BadFormat(
+                    "Connection closed before the end of the headers",
+                ))
+            }
+            read => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = std::str::from_utf8(&buffer[..head_length])
+        .map_err(|_| ReadError::BadFormat("Headers are not valid UTF-8"))?;
+    let header = |name: &str| {
+        head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    };
+
+    // Chunked bodies win over any `Content-Length`, same as in HTTP
+    if let Some(encoding) = header("Transfer-Encoding") {
+        if !encoding.eq_ignore_ascii_case("chunked") {
+            return Err(ReadError::Unsupported(
+                "Only chunked transfer encoding is supported",
+            ));
+        }
+        let body = read_chunks(stream, buffer.split_off(head_length))?;
+        buffer.extend(body);
+        return Packet::from_bytes(buffer).map_err(|e| ReadError::BadFormat(e.0));
+    }
+
+    let content_length = header("Content-Length")
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|_| ReadError::BadFormat("Bad Content-Length"))?;
 
-    // Mark the bytes read as consumed so the buffer will not return them in a subsequent read
-    reader.consume(received.len());
+    if let Some(length) = content_length {
+        if length > MAX_BODY_SIZE {
+            return Err(ReadError::TooLarge("Body is too large"));
+        }
+        let received = buffer.len() - head_length;
+        if received < length {
+            let mut rest = vec![0; length - received];
+            stream.read_exact(&mut rest)?;
+            buffer.extend(rest);
+        }
+        buffer.truncate(head_length + length);
+    }
+
+    Packet::from_bytes(buffer).map_err(|e| ReadError::BadFormat(e.0))
+}
 
-    Ok(received)
+/// Decodes a chunked body. `pending` is whatever was read past the headers already
+fn read_chunks(stream: &mut impl Read, mut pending: Vec<u8>) -> Result<Vec<u8>, ReadError> {
+    let mut body = Vec::new();
+    loop {
+        // Every chunk starts with its size in hex, optionally followed by `;extensions`
+        let line_end = loop {
+            if let Some(end) = pending.windows(2).position(|window| window == b"\r\n") {
+                break end;
+            }
+            if pending.len() > MAX_HEADER_SIZE {
+                return Err(ReadError::BadFormat("Bad chunk size"));
+            }
+            fill(stream, &mut pending)?;
+        };
+        let size = std::str::from_utf8(&pending[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .map(str::trim)
+            .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or(ReadError::BadFormat("Bad chunk size"))?;
+        pending.drain(..line_end + 2);
+
+        // The last chunk is empty. Trailers aren't used, so they're left unread
+        if size == 0 {
+            return Ok(body);
+        }
+        if size > MAX_BODY_SIZE - body.len() {
+            return Err(ReadError::TooLarge("Body is too large"));
+        }
+        while pending.len() < size + 2 {
+            fill(stream, &mut pending)?;
+        }
+        if &pending[size..size + 2] != b"\r\n" {
+            return Err(ReadError::BadFormat("Chunk is longer than its size"));
+        }
+        body.extend(pending.drain(..size));
+        pending.drain(..2);
+    }
+}
+
+/// Reads whatever is available onto the end of `pending`
+fn fill(stream: &mut impl Read, pending: &mut Vec<u8>) -> Result<(), ReadError> {
+    let mut chunk = [0; 4096];
+    match stream.read(&mut chunk)? {
+        0 => Err(ReadError::BadFormat(
+            "Connection closed in the middle of the body",
+        )),
+        read => {
+            pending.extend_from_slice(&chunk[..read]);
+            Ok(())
+        }
+    }
 }
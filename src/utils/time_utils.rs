@@ -0,0 +1,19 @@
+use std::time::{Duration, Instant};
+
+/// Parses the time between wallpaper updates, either as plain seconds or as a humantime duration.
+/// Used for `start -d` and `set-duration` on the client, and for `SETDURATION` on the server
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let duration = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => humantime::parse_duration(value).map_err(|e| e.to_string())?,
+    };
+
+    if duration.is_zero() {
+        return Err("has to be longer than 0s".to_string());
+    }
+    // The server has to be able to tell when it's up
+    if Instant::now().checked_add(duration).is_none() {
+        return Err("is too long".to_string());
+    }
+    Ok(duration)
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobMatcher};
+
+/// Name of the sidecar file that sets how often the wallpapers in its directory (and below) come up
+/// in weighted random mode. For example:
+///
+/// ```text
+/// # Everything in this folder is three times as likely as a wallpaper without a weight
+/// weight = 3
+/// # Patterns are matched against paths relative to this folder. The last match wins
+/// favorite.png = 10
+/// drafts/** = 0
+/// ```
+pub const WEIGHTS_FILE: &str = ".wonderwallweights";
+
+/// Weight of a wallpaper that no weights file says anything about
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// A single parsed `.wonderwallweights` file
+#[derive(Default)]
+struct WeightsFile {
+    /// `weight = N`, applies to every wallpaper in the directory and its subdirectories
+    folder: Option<u32>,
+    /// `<glob> = N` lines, in the order they were written
+    patterns: Vec<(GlobMatcher, u32)>,
+}
+
+impl WeightsFile {
+    /// Reads the weights file in `dir`, if there is one. Bad lines are skipped with a warning
+    fn load(dir: &Path) -> Option<Self> {
+        let path = dir.join(WEIGHTS_FILE);
+        let contents = std::fs::read_to_string(&path).ok()?;
+
+        let mut weights = Self::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Split on the last `=` so patterns can contain one
+            let Some((key, value)) = line.rsplit_once('=') else {
+                log::warn!("{path:?}:{}: expected `<pattern> = <weight>`", number + 1);
+                continue;
+            };
+            let Ok(weight) = value.trim().parse::<u32>() else {
+                log::warn!(
+                    "{path:?}:{}: `{}` is not a weight",
+                    number + 1,
+                    value.trim()
+                );
+                continue;
+            };
+
+            match key.trim() {
+                "weight" => weights.folder = Some(weight),
+                pattern => match Glob::new(pattern) {
+                    Ok(glob) => weights.patterns.push((glob.compile_matcher(), weight)),
+                    Err(e) => log::warn!("{path:?}:{}: {e}", number + 1),
+                },
+            }
+        }
+        Some(weights)
+    }
+
+    /// Weight the last pattern matching `relative` gives it
+    fn pattern_weight(&self, relative: &Path) -> Option<u32> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(relative))
+            .map(|(_, weight)| *weight)
+    }
+}
+
+/// Looks up the weight of every file in `files`, which all have to be inside `root`.
+///
+/// Weights files are read from `root` down to each file's directory. A pattern matching the file
+/// beats any folder's `weight`, and between two patterns (or two folder weights) the deeper one wins.
+pub fn load_weights(root: &Path, files: &[String]) -> Vec<u32> {
+    let mut cache: HashMap<PathBuf, Option<WeightsFile>> = HashMap::new();
+
+    files
+        .iter()
+        .map(|file| {
+            let path = Path::new(file);
+            let Some(parent) = path.parent().filter(|parent| parent.starts_with(root)) else {
+                return DEFAULT_WEIGHT;
+            };
+
+            // Every directory from the file's up to the root, deepest first
+            let dirs: Vec<&Path> = parent
+                .ancestors()
+                .take_while(|dir| dir.starts_with(root))
+                .collect();
+            for dir in &dirs {
+                cache
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| WeightsFile::load(dir));
+            }
+            let weights_files = dirs
+                .iter()
+                .filter_map(|dir| Some((*dir, cache[*dir].as_ref()?)));
+
+            let mut folder_weight = None;
+            for (dir, weights) in weights_files {
+                let relative = path.strip_prefix(dir).unwrap_or(path);
+                if let Some(weight) = weights.pattern_weight(relative) {
+                    return weight;
+                }
+                folder_weight = folder_weight.or(weights.folder);
+            }
+            folder_weight.unwrap_or(DEFAULT_WEIGHT)
+        })
+        .collect()
+}
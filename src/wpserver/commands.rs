@@ -1,157 +1,355 @@
-use std::{io::Write, os::unix::net::UnixStream, path::PathBuf};
+use std::{collections::BTreeMap, io::Write, os::unix::net::UnixStream, time::Instant};
 
 use crate::{
     constants::*,
-    utils::{socket_utils::Packet, *},
+    utils::{
+        file_index::WallpaperIndex, file_utils::ScanOptions, socket_utils::Packet, time_utils,
+    },
 };
 
-use super::server::*;
+use super::{events::Event, messages::*, server::*};
 
 impl WallpaperServer {
-    pub fn set_wp(&mut self, stream: &mut UnixStream, value: String) -> Result<(), ServerError> {
+    pub fn set_wp(&self, request: SetWpRequest) -> Reply {
         log::info!("Received request: SETWP");
+        let SetWpRequest { path, output } = request;
+        if path.trim().is_empty() {
+            return Reply::bad_request("No wallpaper given");
+        }
+        // Catch typos now rather than when the backend fails to load it
+        let readable = std::fs::metadata(&path).is_ok_and(|metadata| metadata.is_file())
+            && std::fs::File::open(&path).is_ok();
+        if !readable {
+            return Reply::bad_request(format!("`{path}` is not a readable file"));
+        }
         let mut data = self.data.lock().unwrap();
 
-        data.next_wallpaper = value.clone();
+        match &output {
+            Some(name) => match data.outputs.get_mut(name) {
+                Some(state) => state.queue(path.clone()),
+                None => return unknown_output(name),
+            },
+            None => data
+                .outputs
+                .values_mut()
+                .for_each(|state| state.queue(path.clone())),
+        }
+        drop(data);
 
         // Trigger wallpaper switch event
-        let (lock, cvar) = &*self.main_trigger;
+        self.trigger(output);
 
-        let mut trigger = lock.lock().unwrap();
-        *trigger = true;
-        cvar.notify_one();
-
-        let response = Packet::new()
-            .method("200")
-            .body(format!("Updated wallpaper to {}", value).as_str());
-
-        stream
-            .write_all(&response.as_bytes())
-            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-        Ok(())
+        Reply::ok(format!("Updated wallpaper to {}", path))
     }
 
-    pub fn get_wp(&mut self, stream: &mut UnixStream) -> Result<(), ServerError> {
+    pub fn get_wp(&self, request: OutputRequest) -> Reply {
         log::info!("Received request: GETWP");
         let data = self.data.lock().unwrap();
 
-        let cur_wp = data.current_wallpaper.clone();
-        let response = Packet::new().method("200").body(&cur_wp);
-        stream
-            .write_all(&response.as_bytes())
-            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-        Ok(())
+        let wallpapers: BTreeMap<String, String> = match &request.output {
+            Some(name) => match data.outputs.get(name) {
+                Some(state) => BTreeMap::from([(name.clone(), state.current_wallpaper.clone())]),
+                None => return unknown_output(name),
+            },
+            None => data
+                .outputs
+                .iter()
+                .map(|(name, state)| (name.clone(), state.current_wallpaper.clone()))
+                .collect(),
+        };
+
+        // With only one queue there's no need to say which output it belongs to
+        let cur_wp = match (&request.output, data.outputs.len()) {
+            (None, 1) | (Some(_), _) => wallpapers.values().cloned().collect(),
+            (None, _) => wallpapers
+                .iter()
+                .map(|(name, path)| format!("{name}: {path}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+
+        Reply::ok(cur_wp).data(WallpapersData { wallpapers })
     }
 
-    pub fn next(&mut self, stream: &mut UnixStream) -> Result<(), ServerError> {
+    pub fn next(&self, request: OutputRequest) -> Reply {
         log::info!("Received request: NEXT");
         let data = self.data.lock().unwrap();
 
-        let (lock, cvar) = &*self.main_trigger;
-        let next_wallpaper = data.current_wallpaper.clone();
+        let next_wallpaper = match &request.output {
+            Some(name) => match data.outputs.get(name) {
+                Some(state) => state.next_wallpaper.clone(),
+                None => return unknown_output(name),
+            },
+            None => data
+                .outputs
+                .values()
+                .map(|state| state.next_wallpaper.clone())
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        drop(data);
 
-        let mut trigger = lock.lock().unwrap();
-        *trigger = true;
-        cvar.notify_one();
+        self.trigger(request.output);
 
-        let response = Packet::new()
-            .method("200")
-            .body(format!("Cycled wallpaper to {}", next_wallpaper).as_str());
-        stream
-            .write_all(&response.as_bytes())
-            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-        Ok(())
+        Reply::ok(format!("Cycled wallpaper to {}", next_wallpaper))
     }
 
-    pub fn get_dir(&mut self, stream: &mut UnixStream) -> Result<(), ServerError> {
-        log::info!("Received request: GETDIR");
-        let data = self.data.lock().unwrap();
+    pub fn pause_cycling(&self, request: PauseRequest) -> Reply {
+        log::info!("Received request: PAUSE");
 
-        let cur_dir = data.directory.clone();
-        let response = Packet::new().method("200").body(&cur_dir);
-        stream
-            .write_all(&response.as_bytes())
-            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-        Ok(())
+        // No duration pauses until resumed
+        let Some(duration) = request.duration else {
+            self.pause(None);
+            return Reply::ok("Paused wallpaper cycling");
+        };
+
+        match humantime::parse_duration(duration.trim()) {
+            Ok(resume_after) if Instant::now().checked_add(resume_after).is_none() => {
+                Reply::bad_request(format!("`{duration}` is too long to pause for"))
+            }
+            Ok(resume_after) => {
+                self.pause(Some(resume_after));
+                Reply::ok(format!(
+                    "Paused wallpaper cycling for {}",
+                    humantime::format_duration(resume_after)
+                ))
+            }
+            Err(e) => Reply::bad_request(format!("Invalid duration `{duration}`: {e}")),
+        }
     }
 
-    pub fn set_dir(&mut self, stream: &mut UnixStream, value: String) -> Result<(), ServerError> {
-        log::info!("Received request: SETDIR");
+    pub fn resume_cycling(&self) -> Reply {
+        log::info!("Received request: RESUME");
+
+        let message = match self.resume() {
+            true => "Resumed wallpaper cycling",
+            false => "Wallpaper cycling was not paused",
+        };
+
+        Reply::ok(message)
+    }
+
+    pub fn toggle_cycling(&self) -> Reply {
+        log::info!("Received request: TOGGLE");
+
+        let message = match self.toggle() {
+            true => "Paused wallpaper cycling",
+            false => "Resumed wallpaper cycling",
+        };
+
+        Reply::ok(message)
+    }
+
+    pub fn set_duration(&self, request: SetDurationRequest) -> Reply {
+        log::info!("Received request: SETDURATION");
+        let value = request.duration.trim();
+
+        // Same rules as `set-duration` on the command line, so plain seconds work too
+        match time_utils::parse_interval(value) {
+            Ok(duration) => {
+                self.set_interval(duration);
+                Reply::ok(format!(
+                    "Wallpaper will now change every {}",
+                    humantime::format_duration(duration)
+                ))
+            }
+            Err(e) => Reply::bad_request(format!("Invalid duration `{value}`: {e}")),
+        }
+    }
+
+    pub fn get_duration(&self) -> Reply {
+        log::info!("Received request: GETDURATION");
+
+        let interval = self.interval();
+        Reply::ok(humantime::format_duration(interval).to_string()).data(DurationData {
+            duration_seconds: interval.as_secs(),
+        })
+    }
+
+    pub fn get_status(&self) -> Reply {
+        log::info!("Received request: STATUS");
+
+        // Plain text clients have always gotten the status as JSON, so they still do
+        let status = self.status();
+        match serde_json::to_string(&status) {
+            Ok(json) => Reply::ok(status.to_string()).data(&status).text(json),
+            Err(e) => Reply::new(500, format!("Could not encode status: {e}")),
+        }
+    }
+
+    pub fn prev(&self, request: OutputRequest) -> Reply {
+        log::info!("Received request: PREV");
         let mut data = self.data.lock().unwrap();
 
-        let mut fields = value.splitn(3, '\n');
+        let previous = match &request.output {
+            Some(name) => match data.outputs.get_mut(name) {
+                Some(state) => state.rewind().map(|path| path.to_string()),
+                None => return unknown_output(name),
+            },
+            // Outputs without a history just keep their wallpaper
+            None => {
+                let previous: Vec<_> = data
+                    .outputs
+                    .values_mut()
+                    .filter_map(|state| state.rewind().map(|path| path.to_string()))
+                    .collect();
+                (!previous.is_empty()).then(|| previous.join(", "))
+            }
+        };
+        drop(data);
 
-        let Some(recursive) = fields.next() else {
-            return Err(ServerError::RequestError("Invalid request format"));
+        match previous {
+            Some(previous) => {
+                self.trigger(request.output);
+                Reply::ok(format!("Went back to {previous}"))
+            }
+            None => Reply::bad_request("There is no previous wallpaper"),
+        }
+    }
+
+    pub fn history(&self, request: HistoryRequest) -> Reply {
+        log::info!("Received request: HISTORY");
+        let data = self.data.lock().unwrap();
+        let count = request.count.unwrap_or(HISTORY_LENGTH);
+
+        // Most recent first
+        let recent = |state: &OutputState| -> Vec<String> {
+            state.history.iter().rev().take(count).cloned().collect()
+        };
+        let history: BTreeMap<String, Vec<String>> = match &request.output {
+            Some(name) => match data.outputs.get(name) {
+                Some(state) => BTreeMap::from([(name.clone(), recent(state))]),
+                None => return unknown_output(name),
+            },
+            None => data
+                .outputs
+                .iter()
+                .map(|(name, state)| (name.clone(), recent(state)))
+                .collect(),
         };
 
-        let Some(random) = fields.next() else {
-            return Err(ServerError::RequestError("Invalid request format"));
+        // With only one queue there's no need to say which output it belongs to
+        let lines = match (&request.output, data.outputs.len()) {
+            (None, 1) | (Some(_), _) => history.values().flatten().cloned().collect::<Vec<_>>(),
+            (None, _) => history
+                .iter()
+                .flat_map(|(name, paths)| paths.iter().map(move |path| format!("{name}: {path}")))
+                .collect(),
         };
 
-        let Some(path) = fields.next() else {
-            return Err(ServerError::RequestError("Invalid request format"));
+        Reply::ok(lines.join("\n")).data(HistoryData { history })
+    }
+
+    pub fn get_dir(&self) -> Reply {
+        log::info!("Received request: GETDIR");
+        let data = self.data.lock().unwrap();
+
+        let directory = data.directory.clone();
+        Reply::ok(directory.clone()).data(DirectoryData { directory })
+    }
+
+    pub fn set_dir(&self, request: SetDirRequest) -> Reply {
+        log::info!("Received request: SETDIR");
+        let data = self.data.lock().unwrap();
+
+        let SetDirRequest {
+            path,
+            recursive,
+            selection,
+            include,
+            exclude,
+            sort,
+        } = request;
+
+        let scan = ScanOptions {
+            recursive,
+            include,
+            exclude,
+            sort: sort.unwrap_or(data.scan.sort),
+            ..data.scan.clone()
         };
+        let force_poll = self.index.lock().unwrap().force_poll;
 
-        data.recursive = recursive.is_empty();
-        data.random = random.is_empty();
+        // Scanning a big directory can take a while, don't block the cycling thread while doing it
+        drop(data);
 
         // Attempt to set the new directory
-        match file_utils::get_directory_files(&PathBuf::from(path), recursive.is_empty()) {
-            Ok(contents) => {
-                // If successful, set the directory, load the first wallpaper, and respond with 200
-                data.directory = path.to_string().clone();
-
-                if let Some(new_first_wallpaper) = contents.first() {
-                    data.current_wallpaper = new_first_wallpaper.clone();
-                    let (lock, cvar) = &*self.main_trigger;
-
-                    let mut trigger = lock.lock().unwrap();
-                    *trigger = true;
-                    cvar.notify_one();
-                    log::info!("Updated wallpaper due to SETDIR request");
-                }
-
-                let response = Packet::new()
-                    .method("200")
-                    .body(format!("Wonderwall will now cycle through {}", path).as_str());
-
-                stream
-                    .write_all(&response.as_bytes())
-                    .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-            }
-            Err(e) => {
-                // If failed, respond with 400
-                let response = Packet::new()
-                    .method("400")
-                    .body(format!("There was an error setting the directory: {e}").as_str());
-                stream
-                    .write_all(&response.as_bytes())
-                    .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+        match WallpaperIndex::new(&path, scan.clone(), force_poll) {
+            Ok(new_index) => {
+                // If successful, set the directory and options, load the first wallpaper, and respond with 200
+                let mut data = self.data.lock().unwrap();
+                data.directory = path.clone();
+                data.scan = scan;
+                data.selection = selection;
+
+                // Start every output over on the new directory. The index is swapped while the data
+                // lock is still held, so the cycling thread never sees the new queues with the old files
+                let outputs = data.outputs.keys().cloned().collect();
+                let weights = selection_weights(selection, &new_index.directory, &new_index.files);
+                data.reset_outputs(outputs, &new_index.files, &weights);
+                let old_index = std::mem::replace(&mut *self.index.lock().unwrap(), new_index);
+                drop(data);
+
+                // The old index (and its watcher) is dropped only after the locks are released
+                drop(old_index);
+                WallpaperIndex::watch(&self.index);
+
+                self.trigger(None);
+                log::info!("Updated wallpaper due to SETDIR request");
+                self.events.lock().unwrap().send(&Event::DirectoryChanged {
+                    directory: path.clone(),
+                });
+
+                Reply::ok(format!("Wonderwall will now cycle through {}", path))
             }
-        };
-        Ok(())
+            // If failed, respond with 400
+            Err(e) => Reply::bad_request(format!("There was an error setting the directory: {e}")),
+        }
     }
 
-    pub fn kill(&mut self, stream: &mut UnixStream) -> Result<(), ServerError> {
+    /// Replies, then `handle_stream()` stops the server
+    pub fn kill(&self) -> Reply {
         log::info!("Received request: KILL");
+        Reply::ok("Stopping server...")
+    }
 
-        let response = Packet::new().method("200").body("Stopping server...");
+    pub fn ping(&self) -> Reply {
+        log::info!("Received request: PING");
+        Reply::ok("pong")
+    }
 
+    /// Keeps the connection open and sends every `Event` down it as a line of JSON, until the
+    /// client hangs up
+    pub fn subscribe(&self, mut stream: UnixStream) -> Result<(), ServerError<'_>> {
+        log::info!("Received request: SUBSCRIBE");
+        let mut events = self.events.lock().unwrap();
+
+        // Subscribers don't hold on to a connection slot, so they're capped separately
+        if events.is_full() {
+            let response = Packet::new()
+                .method("503")
+                .body("Too many event subscribers, try again later");
+            stream
+                .write_all(&response.as_bytes())
+                .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+            return Err(ServerError::RequestError("Too many event subscribers"));
+        }
+
+        // No `Content-Length`, the body goes on until the connection closes
+        let response = Packet::new()
+            .method("200")
+            .header("Content-Type", "application/x-ndjson");
         stream
-            .write_all(&response.as_bytes())
+            .write_all(&response.head_bytes())
             .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
 
-        Err(ServerError::Kill)
+        events
+            .add(stream)
+            .map_err(|_| ServerError::SocketError("Failed to add event subscriber"))
     }
+}
 
-    #[allow(unused)]
-    pub fn ping(&mut self, stream: &mut UnixStream) -> Result<(), ServerError> {
-        log::info!("Received request: PING");
-
-        let response = Packet::new().method("200").body("pong");
-        stream
-            .write_all(&response.as_bytes())
-            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))
-    }
+/// Responds with a 400 for a request aimed at an output the server doesn't know about
+fn unknown_output(name: &str) -> Reply {
+    Reply::bad_request(format!("Unknown output `{name}`"))
 }
@@ -0,0 +1,114 @@
+use std::{
+    fmt::Display,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::MAX_SUBSCRIBERS;
+
+/// Something that happened, sent to every `SUBSCRIBE`d connection as a line of JSON, e.g.
+/// `{"event":"wallpaper_changed","output":"DP-1","path":"/home/me/walls/a.png"}`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// `output` is empty when the backend shows the same wallpaper on every screen
+    WallpaperChanged {
+        output: String,
+        path: String,
+    },
+    DirectoryChanged {
+        directory: String,
+    },
+    /// `resume_seconds` is only set for a timed pause
+    Paused {
+        resume_seconds: Option<u64>,
+    },
+    Resumed,
+    Error {
+        message: String,
+    },
+    /// The server is stopping, nothing else will be sent
+    Shutdown,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::WallpaperChanged { output, path } if output.is_empty() => {
+                write!(f, "Wallpaper changed to {path}")
+            }
+            Event::WallpaperChanged { output, path } => {
+                write!(f, "Wallpaper on {output} changed to {path}")
+            }
+            Event::DirectoryChanged { directory } => write!(f, "Now cycling through {directory}"),
+            Event::Paused {
+                resume_seconds: Some(seconds),
+            } => write!(
+                f,
+                "Paused for {}",
+                humantime::format_duration(Duration::from_secs(*seconds))
+            ),
+            Event::Paused {
+                resume_seconds: None,
+            } => f.write_str("Paused"),
+            Event::Resumed => f.write_str("Resumed"),
+            Event::Error { message } => write!(f, "Error: {message}"),
+            Event::Shutdown => f.write_str("Server stopped"),
+        }
+    }
+}
+
+/// Connections that asked to be told about `Event`s
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    streams: Vec<UnixStream>,
+}
+
+pub type SharedSubscribers = Arc<Mutex<Subscribers>>;
+
+impl Subscribers {
+    /// Whether there's no room for another subscriber. Ones that hung up since the last event don't
+    /// count
+    pub fn is_full(&mut self) -> bool {
+        // Subscribers never send anything, so a read only ever finds out whether they hung up
+        self.streams
+            .retain(|mut stream| match stream.read(&mut [0; 64]) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => true,
+                Ok(0) | Err(_) => false,
+                Ok(_) => true,
+            });
+        self.streams.len() >= MAX_SUBSCRIBERS
+    }
+
+    /// Starts sending events to `stream`. Writes to it won't block from here on
+    pub fn add(&mut self, stream: UnixStream) -> std::io::Result<()> {
+        stream.set_nonblocking(true)?;
+        self.streams.push(stream);
+        Ok(())
+    }
+
+    /// Sends `event` to every subscriber. The ones that hung up (or stopped reading and let the
+    /// socket's buffer fill up) are dropped
+    pub fn send(&mut self, event: &Event) {
+        if self.streams.is_empty() {
+            return;
+        }
+        let Ok(mut line) = serde_json::to_string(event) else {
+            return;
+        };
+        line.push('\n');
+
+        self.streams
+            .retain_mut(|stream| match stream.write_all(line.as_bytes()) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::info!("Dropping event subscriber: {e}");
+                    false
+                }
+            });
+    }
+}
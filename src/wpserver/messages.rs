@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::{file_utils::SortMode, socket_utils::Packet};
+
+use super::server::SelectionMode;
+
+// Typed bodies for every command. Requests sent with `Content-Type: application/json` are read as
+// these, and get a JSON `Reply` back. Anything else goes through `from_legacy()`, which understands
+// the plain text bodies older clients (and curl users) send
+
+/// Request for commands that don't take any parameters
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmptyRequest {}
+
+/// Request for `GETWP`, `NEXT` and `PREV`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputRequest {
+    /// Only this output. `None` means every output
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetWpRequest {
+    pub path: String,
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryRequest {
+    /// How many wallpapers to list. `None` lists all of them
+    pub count: Option<usize>,
+    pub output: Option<String>,
+}
+
+impl HistoryRequest {
+    /// The body holds the count, or nothing for all of them
+    pub fn from_legacy(body: &str, output: Option<String>) -> Result<Self, String> {
+        let count = match body.trim() {
+            "" => None,
+            count => Some(
+                count
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid count `{count}`"))?,
+            ),
+        };
+        Ok(Self { count, output })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PauseRequest {
+    /// How long to pause for, e.g. `30m`. `None` pauses until resumed
+    pub duration: Option<String>,
+}
+
+impl PauseRequest {
+    /// The body holds the duration, or nothing to pause until resumed
+    pub fn from_legacy(body: &str) -> Self {
+        let duration = Some(body.trim())
+            .filter(|duration| !duration.is_empty())
+            .map(str::to_string);
+        Self { duration }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetDurationRequest {
+    /// The new interval, e.g. `15m`
+    pub duration: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetDirRequest {
+    pub path: String,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub selection: SelectionMode,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// `None` keeps the current sort order
+    #[serde(default)]
+    pub sort: Option<SortMode>,
+}
+
+impl SetDirRequest {
+    /// The body is `recursive\nselection\ninclude\nexclude\nsort\npath`, with an empty string for
+    /// `false`/defaults and tab separated globs. The oldest clients only send
+    /// `recursive\nrandom\npath`, and a body without newlines is just the path
+    pub fn from_legacy(body: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = body.splitn(6, '\n').collect();
+        let [recursive, selection, include, exclude, sort, path] = match fields[..] {
+            [recursive, selection, include, exclude, sort, path] => {
+                [recursive, selection, include, exclude, sort, path]
+            }
+            [recursive, random, path] => [recursive, random, "", "", "", path],
+            _ => ["", "", "", "", "", body.trim()],
+        };
+
+        // The oldest clients send `true` for random mode
+        let selection = match selection {
+            "" => SelectionMode::Sequential,
+            "true" => SelectionMode::Shuffle,
+            selection => SelectionMode::from_str(selection, true)?,
+        };
+        let sort = match sort {
+            "" => None,
+            sort => Some(SortMode::from_str(sort, true)?),
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            recursive: !recursive.is_empty(),
+            selection,
+            include: split_globs(include),
+            exclude: split_globs(exclude),
+            sort,
+        })
+    }
+}
+
+/// Splits a tab separated list of globs
+fn split_globs(globs: &str) -> Vec<String> {
+    globs
+        .split('\t')
+        .filter(|glob| !glob.is_empty())
+        .map(|glob| glob.to_string())
+        .collect()
+}
+
+/// `data` of a `GETWP` reply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WallpapersData {
+    /// Current wallpaper of every output, by name
+    pub wallpapers: BTreeMap<String, String>,
+}
+
+/// `data` of a `HISTORY` reply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryData {
+    /// Wallpapers shown on every output, by name, most recent first
+    pub history: BTreeMap<String, Vec<String>>,
+}
+
+/// `data` of a `GETDIR` reply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryData {
+    pub directory: String,
+}
+
+/// `data` of a `GETDURATION` reply
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DurationData {
+    pub duration_seconds: u64,
+}
+
+/// What the server sends back for every command
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reply {
+    /// HTTP style status code. It goes in the status line, not the body
+    #[serde(skip)]
+    pub status: u16,
+    /// What happened, for people. This is the whole reply for plain text clients
+    pub message: String,
+    /// Typed data for the commands that return some (`WallpapersData` for `GETWP`, and so on)
+    #[serde(default)]
+    pub data: Value,
+    /// What plain text clients get instead of `message`, when that's different
+    #[serde(skip)]
+    pub text: Option<String>,
+}
+
+impl Reply {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            data: Value::Null,
+            text: None,
+        }
+    }
+
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self::new(200, message)
+    }
+
+    /// The request was the problem, e.g. an unknown output or a bad duration
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    /// Sets data
+    pub fn data(mut self, data: impl Serialize) -> Self {
+        self.data = serde_json::to_value(data).unwrap_or(Value::Null);
+        self
+    }
+
+    /// Sets what plain text clients get instead of the message
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Whether the server carried out the request (a 2xx status)
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Builds the response packet, either as JSON or as plain text
+    pub fn packet(&self, json: bool) -> Packet {
+        let response = Packet::new().method(&self.status.to_string());
+        match (json, serde_json::to_string(self)) {
+            (true, Ok(body)) => response
+                .header("Content-Type", "application/json")
+                .body(&body),
+            _ => response.body(self.text.as_ref().unwrap_or(&self.message)),
+        }
+    }
+}
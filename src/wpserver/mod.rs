@@ -1,2 +1,8 @@
 pub mod commands;
+pub mod events;
+pub mod messages;
 pub mod server;
+pub mod status;
+
+#[cfg(test)]
+mod tests;
@@ -1,62 +1,162 @@
 use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
     error::Error,
     fmt::Display,
-    io::{BufReader, Write},
-    os::unix::net::{UnixListener, UnixStream},
+    fs::Permissions,
+    io::{ErrorKind, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
     path::{Path, PathBuf},
-    sync::{Arc, Condvar, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    events::{Event, SharedSubscribers},
+    messages::*,
+    status::{OutputStatus, Status},
+};
 use crate::{
+    backends::{BackendKind, BackendOptions, SharedBackend, WallpaperBackend, ALL_OUTPUTS},
     constants::*,
-    utils::{socket_utils::Packet, *},
+    utils::{
+        file_index::{SharedIndex, WallpaperIndex},
+        file_utils::ScanOptions,
+        socket_utils::{DeadlineReader, Packet, ReadError, ROUTES},
+        *,
+    },
 };
 
 /// Options the user can pass in to WallpaperServer::new()
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct WallpaperOptions {
     pub directory: String,
-    pub duration: u64,
-    pub recursive: bool,
-    pub random: bool,
+    /// Time between automatic wallpaper changes
+    pub duration: Duration,
+    pub scan: ScanOptions,
+    /// Rescan the directory every cycle instead of watching it for changes
+    pub poll: bool,
+    pub selection: SelectionMode,
+    pub backend: BackendKind,
+    pub backend_options: BackendOptions,
+    pub outputs: Vec<String>,
+    /// Where to listen for requests
+    pub socket: PathBuf,
 }
 
-pub struct WallpaperData {
-    pub directory: String,
+/// How the next wallpaper is picked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionMode {
+    /// One after the other, in `--sort` order
+    #[default]
+    Sequential,
+    /// Random order, but every wallpaper is shown once before any of them is shown again
+    Shuffle,
+    /// Random, with wallpapers picked more or less often depending on their `.wonderwallweights`
+    Weighted,
+}
+
+/// How many previously shown wallpapers each output remembers
+pub const HISTORY_LENGTH: usize = 100;
+
+/// Wallpaper queue of a single output
+#[derive(Debug, Default)]
+pub struct OutputState {
     pub current_wallpaper: String,
     pub next_wallpaper: String,
-    pub recursive: bool,
-    pub random: bool,
     pub index: usize,
+    /// Wallpapers shown before the current one, oldest first. Holds at most `HISTORY_LENGTH`
+    pub history: VecDeque<String>,
+    /// Set by `rewind()`, so the wallpaper being left isn't added to the history
+    rewinding: bool,
+    /// Shuffle mode only: wallpapers left to show this round, in the (shuffled) order they'll be shown
+    /// in, back to front
+    shuffle_bag: Vec<String>,
+    /// Shuffle mode only: every wallpaper that was part of this round, shown or not. Anything not in
+    /// here is new since the bag was filled
+    shuffle_round: HashSet<String>,
+}
+
+pub struct WallpaperData {
+    pub directory: String,
+    pub scan: ScanOptions,
+    pub selection: SelectionMode,
+    /// Queues keyed by output name. If the backend can't tell outputs apart, this only holds
+    /// `ALL_OUTPUTS`, whose wallpaper is shown on every screen
+    pub outputs: BTreeMap<String, OutputState>,
+    /// Set when the user listed the outputs at startup, in which case the backend is never asked for them
+    pub pinned_outputs: bool,
+}
+
+/// Controls when the cycling thread wakes up
+#[derive(Debug, Default)]
+pub struct Schedule {
+    /// Outputs that were manually cycled since the thread last woke up. `None` stands for every output
+    pub requests: Vec<Option<String>>,
+    /// Stops automatic cycling. Manual requests still go through
+    pub paused: bool,
+    /// When a timed pause ends on its own
+    pub resume_at: Option<Instant>,
+    /// Time between automatic wallpaper changes. Can be changed while the server is running
+    pub duration: Duration,
+    /// When the cycling thread will next wake up on its own. `None` while paused indefinitely
+    pub next_cycle: Option<Instant>,
 }
 
 pub struct WallpaperServer {
-    pub duration: u64,
-    pub main_trigger: Arc<(Mutex<bool>, Condvar)>,
+    pub main_trigger: Arc<(Mutex<Schedule>, Condvar)>,
     pub data: Arc<Mutex<WallpaperData>>,
+    pub backend: SharedBackend,
+    pub index: SharedIndex,
+    /// Connections that `SUBSCRIBE`d to events
+    pub events: SharedSubscribers,
+    /// When the server was created, for its uptime
+    started: Instant,
+    /// Path of the socket `run()` listens on
+    socket: PathBuf,
+    /// Whether `run()` bound the socket, and so is responsible for cleaning it up
+    owns_socket: bool,
 }
 
 impl Drop for WallpaperServer {
     fn drop(&mut self) {
-        log::warn!("Removing file {}", FILE_SOCKET);
-        std::fs::remove_file(FILE_SOCKET).expect("Failed to remove socket file.");
+        if self.owns_socket {
+            log::warn!("Removing file {}", self.socket.display());
+            std::fs::remove_file(&self.socket).expect("Failed to remove socket file.");
+        }
     }
 }
 
 impl WallpaperServer {
     /// Initializes a `WallpaperServer` instance with a backgrounds directory. The server can then be started with `.start()`
-    pub fn new(
-        WallpaperOptions {
-            directory,
-            duration,
-            recursive,
-            random,
-        }: WallpaperOptions,
-    ) -> Result<Self, Box<dyn Error>> {
-        // If the path exists, try pinging the server
-        if Path::new(&FILE_SOCKET).exists() {
-            if socket_utils::send_request("PING", "", FILE_SOCKET)
-                .is_ok_and(|response| response.trim() == "pong")
+    pub fn new(options: WallpaperOptions) -> Result<Self, Box<dyn Error>> {
+        // Never delete something that isn't a socket, it's most likely a typo in `--socket`
+        let existing = match options.socket.symlink_metadata() {
+            Ok(metadata) => Some(metadata.file_type()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(Box::new(e)),
+        };
+        if existing.is_some_and(|file_type| !file_type.is_socket()) {
+            log::error!("{} exists and is not a socket!", options.socket.display());
+            return Err(Box::new(ServerError::SocketError(
+                "Socket path exists and is not a socket!",
+            )));
+        }
+
+        // If the socket exists, try pinging the server
+        if existing.is_some() {
+            if socket_utils::send_request("PING", "", &options.socket)
+                .is_ok_and(|response| response.body.trim() == "pong")
             {
                 // If the server responds, it means its running, so we back off
                 log::error!("Server is alraedy running on socket!");
@@ -66,53 +166,195 @@ impl WallpaperServer {
             } else {
                 // If the server did not respond, it was most likely improperly terminated, so we take over
                 log::warn!("Socket file was detected, but server did not respond to ping. Deleting socket and starting server...");
-                std::fs::remove_file(FILE_SOCKET).unwrap();
+                std::fs::remove_file(&options.socket)?;
             }
         }
 
+        let backend = options.backend.create(options.backend_options.clone())?;
+        Self::with_backend(options, backend)
+    }
+
+    /// Same as `new()`, but with an already created backend and without checking the socket for another server
+    pub fn with_backend(
+        WallpaperOptions {
+            directory,
+            duration,
+            scan,
+            poll,
+            selection,
+            outputs,
+            socket,
+            ..
+        }: WallpaperOptions,
+        mut backend: Box<dyn WallpaperBackend>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Err(e) = backend.health_check() {
+            // The backend's daemon might just not be up yet (e.g. both are launched on login), so only warn
+            log::warn!("Backend {} failed its health check: {e}", backend.name());
+        }
+
         // Read the directory
-        let wallpapers = file_utils::get_directory_files(&PathBuf::from(&directory), recursive)?;
+        let index = WallpaperIndex::new(&directory, scan.clone(), poll)?;
 
-        let first_index = match random {
-            true => rand::random_range(..wallpapers.len()),
-            false => 0,
+        let mut data = WallpaperData {
+            directory,
+            scan,
+            selection,
+            outputs: BTreeMap::new(),
+            pinned_outputs: !outputs.is_empty(),
         };
-
-        let second_index = match random {
-            true => {
-                let mut second_index = rand::random_range(..wallpapers.len());
-                while second_index == first_index {
-                    second_index = rand::random_range(..wallpapers.len())
-                }
-                second_index
-            }
-            false => 1,
+        let outputs = match data.pinned_outputs {
+            true => outputs,
+            false => query_outputs(backend.as_mut()),
         };
+        log::info!("Cycling wallpapers on outputs: {outputs:?}");
+        let weights = selection_weights(selection, &index.directory, &index.files);
+        data.reset_outputs(outputs, &index.files, &weights);
 
-        let first_wallpaper = wallpapers
-            .get(first_index)
-            .unwrap_or(&String::new())
-            .clone();
-
-        let second_wallpaper = wallpapers
-            .get(second_index)
-            .unwrap_or(&String::new())
-            .clone();
+        let schedule = Schedule {
+            duration,
+            ..Default::default()
+        };
 
         Ok(WallpaperServer {
-            main_trigger: Arc::new((Mutex::new(false), Condvar::new())),
-            duration,
-            data: Arc::new(Mutex::new(WallpaperData {
-                directory,
-                current_wallpaper: first_wallpaper,
-                next_wallpaper: second_wallpaper,
-                recursive,
-                random,
-                index: 0,
-            })),
+            main_trigger: Arc::new((Mutex::new(schedule), Condvar::new())),
+            data: Arc::new(Mutex::new(data)),
+            backend: Arc::new(Mutex::new(backend)),
+            index: Arc::new(Mutex::new(index)),
+            events: SharedSubscribers::default(),
+            started: Instant::now(),
+            socket,
+            owns_socket: false,
         })
     }
 
+    /// Wakes up the cycling thread so it immediately cycles `output`, or every output if `None`
+    pub fn trigger(&self, output: Option<String>) {
+        let (lock, cvar) = &*self.main_trigger;
+
+        let mut schedule = lock.lock().unwrap();
+        schedule.requests.push(output);
+        cvar.notify_one();
+    }
+
+    /// Stops automatic cycling, for `resume_after` or until `resume()` is called
+    pub fn pause(&self, resume_after: Option<Duration>) {
+        let (lock, cvar) = &*self.main_trigger;
+
+        let mut schedule = lock.lock().unwrap();
+        schedule.paused = true;
+        // `pause_cycling()` turns away pauses too long to fit in an `Instant`, anything that still
+        // doesn't fit might as well be paused until resumed
+        schedule.resume_at = resume_after.and_then(|duration| Instant::now().checked_add(duration));
+        cvar.notify_one();
+        drop(schedule);
+
+        self.events.lock().unwrap().send(&Event::Paused {
+            resume_seconds: resume_after.map(|duration| duration.as_secs()),
+        });
+    }
+
+    /// Restarts automatic cycling. The next wallpaper change is a full `duration` away. Returns whether
+    /// it was paused
+    pub fn resume(&self) -> bool {
+        let (lock, cvar) = &*self.main_trigger;
+
+        let mut schedule = lock.lock().unwrap();
+        let was_paused = std::mem::replace(&mut schedule.paused, false);
+        schedule.resume_at = None;
+        cvar.notify_one();
+        drop(schedule);
+
+        if was_paused {
+            self.events.lock().unwrap().send(&Event::Resumed);
+        }
+        was_paused
+    }
+
+    /// `resume()` if paused, otherwise `pause()` until resumed. Returns whether it's now paused
+    pub fn toggle(&self) -> bool {
+        let (lock, cvar) = &*self.main_trigger;
+
+        // Read and flip under the same lock, so two toggles at once can't both pause
+        let mut schedule = lock.lock().unwrap();
+        schedule.paused = !schedule.paused;
+        schedule.resume_at = None;
+        let paused = schedule.paused;
+        cvar.notify_one();
+        drop(schedule);
+
+        self.events.lock().unwrap().send(&match paused {
+            true => Event::Paused {
+                resume_seconds: None,
+            },
+            false => Event::Resumed,
+        });
+        paused
+    }
+
+    /// Changes the time between automatic wallpaper changes. The time already waited counts towards it
+    pub fn set_interval(&self, duration: Duration) {
+        let (lock, cvar) = &*self.main_trigger;
+
+        let mut schedule = lock.lock().unwrap();
+        schedule.duration = duration;
+        cvar.notify_one();
+    }
+
+    /// Time between automatic wallpaper changes
+    pub fn interval(&self) -> Duration {
+        self.main_trigger.0.lock().unwrap().duration
+    }
+
+    /// Takes a snapshot of the server's state. Each lock is only held long enough to copy out of it
+    pub fn status(&self) -> Status {
+        let backend = self.backend.lock().unwrap().name().to_string();
+        let (image_count, watching) = {
+            let index = self.index.lock().unwrap();
+            (index.files.len(), !index.polling)
+        };
+
+        let now = Instant::now();
+        let seconds_until =
+            |instant: Instant| instant.saturating_duration_since(now).as_secs_f64().round() as u64;
+        let (interval, paused, next_change, resume) = {
+            let schedule = self.main_trigger.0.lock().unwrap();
+            let next_change = match schedule.paused {
+                true => None,
+                false => schedule.next_cycle.map(seconds_until),
+            };
+            let resume = schedule.resume_at.map(seconds_until);
+            (schedule.duration, schedule.paused, next_change, resume)
+        };
+
+        let data = self.data.lock().unwrap();
+        Status {
+            backend,
+            directory: data.directory.clone(),
+            recursive: data.scan.recursive,
+            selection: data.selection,
+            sort: data.scan.sort,
+            image_count,
+            watching,
+            interval_seconds: interval.as_secs(),
+            next_change_seconds: next_change,
+            paused,
+            resume_seconds: resume,
+            uptime_seconds: self.started.elapsed().as_secs(),
+            outputs: data
+                .outputs
+                .iter()
+                .map(|(name, state)| {
+                    let status = OutputStatus {
+                        current_wallpaper: state.current_wallpaper.clone(),
+                        next_wallpaper: state.next_wallpaper.clone(),
+                    };
+                    (name.clone(), status)
+                })
+                .collect(),
+        }
+    }
+
     /// Starts the Wallpaper socket server.
     ///
     /// If the server is terminated with a `Stop` via Unix Socket request, this function will return `Ok(())`.
@@ -122,190 +364,750 @@ impl WallpaperServer {
         // Set up Atomic Mutexes for the child thread to use
         let child_trigger = self.main_trigger.clone();
         let child_data = self.data.clone();
-        let duration = self.duration;
+        let child_backend = self.backend.clone();
+        let child_index = self.index.clone();
+        let child_socket = self.socket.clone();
+        let child_events = self.events.clone();
+
+        // Watching has to start here rather than in `new()`, the watcher's thread wouldn't survive daemonizing
+        WallpaperIndex::watch(&self.index);
 
         // Spawn the child thread. This thread will be responsible for cycling the wallpaper every DURATION seconds
         std::thread::spawn(move || -> ! {
+            // Every output gets a wallpaper right away
+            let mut requests = vec![None];
             loop {
-                match cycle_wallpapers(duration, &child_trigger, &child_data) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::warn!("Ran into error: {e}");
-                        match e {
-                            ServerError::FileError(msg) => {
-                                if msg != "Empty directory" {
-                                    log::error!("FATAL ERROR. Terminating...");
-                                    std::fs::remove_file(FILE_SOCKET)
-                                        .expect("Failed to remove socket file.");
-                                    std::process::exit(1);
-                                }
-                            }
-                            ServerError::HyprpaperError => {
-                                log::error!("FATAL ERROR. Terminating...");
-                                std::fs::remove_file(FILE_SOCKET)
-                                    .expect("Failed to remove socket file.");
-                                std::process::exit(1);
+                if let Err(e) = cycle_wallpapers(
+                    &requests,
+                    &child_data,
+                    &child_backend,
+                    &child_index,
+                    &child_events,
+                ) {
+                    log::warn!("Ran into error: {e}");
+                    child_events.lock().unwrap().send(&Event::Error {
+                        message: e.to_string(),
+                    });
+                    match e {
+                        ServerError::FileError(msg) if msg != "Empty directory" => {
+                            terminate(&child_backend, &child_events, &child_socket)
+                        }
+                        ServerError::BackendError => {
+                            // A healthy backend means only this image was the problem, so we just move on
+                            let health = child_backend.lock().unwrap().health_check();
+                            if let Err(e) = health {
+                                log::error!("Backend failed health check: {e}");
+                                terminate(&child_backend, &child_events, &child_socket);
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
                 }
+
+                requests = wait_for_trigger(&child_trigger, &child_events);
             }
         });
 
-        let listener = UnixListener::bind(FILE_SOCKET)?;
+        let result = self.listen();
 
-        log::info!("Starting server at {}", FILE_SOCKET);
+        self.events.lock().unwrap().send(&Event::Shutdown);
+        self.backend.lock().unwrap().shutdown();
+        result
+    }
 
-        // Start listening for requests on the File socket!
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    if let Err(error_type) = self.handle_stream(stream) {
-                        match error_type {
-                            ServerError::Kill => {
-                                log::warn!("Stopping server...");
-                                // Break out of the socket listener loop so we can exit gracefully through `main()`
-                                break;
-                            }
-                            e => {
-                                log::error!("{e}");
-                            }
-                        }
-                    };
+    /// Binds the socket and handles requests until one of them is a `KILL`
+    fn listen(&mut self) -> Result<(), Box<dyn Error>> {
+        let listener = UnixListener::bind(&self.socket)?;
+        self.owns_socket = true;
+        // Only the user running the server gets to control it
+        std::fs::set_permissions(&self.socket, Permissions::from_mode(0o600))?;
+
+        log::info!("Starting server at {}", self.socket.display());
+
+        // Every connection gets its own thread, so a client that never sends its request only holds
+        // up itself. They all have to finish before the scope ends, which `REQUEST_TIMEOUT` bounds
+        let server = &*self;
+        let stopping = AtomicBool::new(false);
+        let connections = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            // Start listening for requests on the File socket!
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::SeqCst) {
+                    break;
                 }
-                Err(e) => {
-                    log::error!("Ran into an error when handling request: {e}");
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::error!("Ran into an error when handling request: {e}");
+                        continue;
+                    }
+                };
+
+                if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    log::warn!(
+                        "Turning away a client, already handling {MAX_CONNECTIONS} connections"
+                    );
+                    let response = Packet::new()
+                        .method("503")
+                        .body("Too many connections, try again later");
+                    let _ = stream
+                        .set_write_timeout(Some(REQUEST_TIMEOUT))
+                        .and_then(|_| stream.write_all(&response.as_bytes()));
                     continue;
                 }
-            };
-        }
+
+                let (stopping, connections) = (&stopping, &connections);
+                scope.spawn(move || {
+                    match server.handle_stream(stream) {
+                        Ok(()) => {}
+                        Err(ServerError::Kill) => {
+                            log::warn!("Stopping server...");
+                            // The listener only checks once it accepts a connection, so give it one.
+                            // It then breaks out of the loop so we can exit gracefully through `main()`
+                            stopping.store(true, Ordering::SeqCst);
+                            let _ = UnixStream::connect(&server.socket);
+                        }
+                        Err(e) => log::error!("{e}"),
+                    }
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
         Ok(())
     }
 
     /// Reads the raw request from socket bytestream, decodes the packet, and submits the request to be processed.
-    fn handle_stream(&mut self, mut stream: UnixStream) -> Result<(), ServerError> {
-        // Read bytes into the buffer using a reader
-        let mut reader = BufReader::new(stream.try_clone().unwrap());
-        let Ok(buffer) = socket_utils::extract_bytes_buffered(&mut reader) else {
-            // Reading bytes is an internal error
-            let response = Packet::new().method("300").body("Internal server error");
-            stream
-                .write_all(&response.as_bytes())
-                .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-
-            return Err(ServerError::RequestError(
-                "Error while attempting to read from File Socket stream",
-            ));
+    pub(crate) fn handle_stream(&self, mut stream: UnixStream) -> Result<(), ServerError<'_>> {
+        // A client that stops sending (or reading) shouldn't be able to hold up its thread forever
+        stream
+            .set_write_timeout(Some(REQUEST_TIMEOUT))
+            .map_err(|_| ServerError::SocketError("Failed to set socket timeouts"))?;
+        let mut reader = DeadlineReader {
+            stream: &stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        };
+
+        let request = match socket_utils::read_packet(&mut reader) {
+            Ok(request) => request,
+            Err(e) => {
+                // Anything but a timeout or an oversized packet means the client sent garbage
+                let status = match &e {
+                    ReadError::Io(e)
+                        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    {
+                        "408"
+                    }
+                    ReadError::TooLarge(_) => "413",
+                    ReadError::Unsupported(_) => "501",
+                    _ => "400",
+                };
+                log::warn!("Could not read request: {e}");
+                let response = Packet::new()
+                    .method(status)
+                    .body(&format!("Could not read request: {e}"));
+                stream
+                    .write_all(&response.as_bytes())
+                    .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+
+                return Err(ServerError::RequestError("Could not read request"));
+            }
         };
 
         log::info!(
             "Request received\n`{}`",
-            String::from_utf8(buffer.clone()).unwrap()
+            String::from_utf8_lossy(&request.as_bytes())
         );
 
-        let Ok(request) = Packet::from_bytes(buffer) else {
-            // Bad packet format is a user error
-            let response = Packet::new().method("400").body("Request has bad format");
-            stream
-                .write_all(&response.as_bytes())
-                .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+        // The `WallpaperControl` header is how older clients name the command, and wins if it's there.
+        // Commands that can target a single output take it from a separate header
+        let (command, body, output) = match request.get_header("WallpaperControl") {
+            Some(command) => (
+                command.to_uppercase(),
+                request.body.clone(),
+                request.get_header("WallpaperOutput").map(str::to_string),
+            ),
+            None => match route(&request) {
+                Ok(routed) => routed,
+                // Unknown route is a user error
+                Err(response) => {
+                    stream
+                        .write_all(&response.as_bytes())
+                        .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
 
-            return Err(ServerError::RequestError("Packet has bad format"));
+                    return Err(ServerError::RequestError("Request has no command or route"));
+                }
+            },
         };
 
-        let command = match request.headers.get("WallpaperControl") {
-            Some(command) => command,
-            None => {
-                // Bad packet format is a user error
-                let response = Packet::new().method("400").body("Missing required headers");
-                stream
-                    .write_all(&response.as_bytes())
-                    .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
-
-                return Err(ServerError::RequestError(
-                    "Packet is missing required headers",
-                ));
-            }
+        // A JSON body holds the command's typed request. JSON requests get a JSON reply, as does
+        // anyone who asks for one
+        let has_json = |header| {
+            request
+                .get_header(header)
+                .is_some_and(|value| value.contains("application/json"))
         };
+        let json_body = has_json("Content-Type");
+        let json_reply = json_body || has_json("Accept");
 
         // Handle Wallpaper command
-        match command.to_uppercase().as_str() {
-            "GETWP" => self.get_wp(&mut stream)?,
-            "SETWP" => self.set_wp(&mut stream, request.body)?,
-            "NEXT" => self.next(&mut stream)?,
-            "GETDIR" => self.get_dir(&mut stream)?,
-            "SETDIR" => self.set_dir(&mut stream, request.body)?,
-            "KILL" => self.kill(&mut stream)?,
-            "PING" => self.ping(&mut stream)?,
-            invalid_request => {
-                log::warn!("Received invalid request: {invalid_request}");
-
-                // Invalid request is a user error
-                let response = Packet::new().method("400").body("Invalid request!");
-                stream
-                    .write_all(&response.as_bytes())
-                    .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+        let reply =
+            match command.as_str() {
+                "GETWP" => parse(json_body, &body, || Ok(OutputRequest { output }))
+                    .map(|request| self.get_wp(request)),
+                "SETWP" => parse(json_body, &body, || {
+                    Ok(SetWpRequest {
+                        path: body.clone(),
+                        output,
+                    })
+                })
+                .map(|request| self.set_wp(request)),
+                "NEXT" => parse(json_body, &body, || Ok(OutputRequest { output }))
+                    .map(|request| self.next(request)),
+                "PAUSE" => parse(json_body, &body, || Ok(PauseRequest::from_legacy(&body)))
+                    .map(|request| self.pause_cycling(request)),
+                "RESUME" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.resume_cycling()),
+                "TOGGLE" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.toggle_cycling()),
+                "SETDURATION" => parse(json_body, &body, || {
+                    Ok(SetDurationRequest {
+                        duration: body.clone(),
+                    })
+                })
+                .map(|request| self.set_duration(request)),
+                "GETDURATION" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.get_duration()),
+                "STATUS" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.get_status()),
+                "PREV" => parse(json_body, &body, || Ok(OutputRequest { output }))
+                    .map(|request| self.prev(request)),
+                "HISTORY" => parse(json_body, &body, || {
+                    HistoryRequest::from_legacy(&body, output)
+                })
+                .map(|request| self.history(request)),
+                "GETDIR" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.get_dir()),
+                "SETDIR" => parse(json_body, &body, || SetDirRequest::from_legacy(&body))
+                    .map(|request| self.set_dir(request)),
+                "KILL" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.kill()),
+                "PING" => parse(json_body, &body, || Ok(EmptyRequest {}))
+                    .map(|EmptyRequest {}| self.ping()),
+                // The connection stays open for events, so this one writes its own response
+                "SUBSCRIBE" => match parse(json_body, &body, || Ok(EmptyRequest {})) {
+                    Ok(EmptyRequest {}) => return self.subscribe(stream),
+                    Err(reply) => Err(reply),
+                },
+                invalid_request => {
+                    log::warn!("Received invalid request: {invalid_request}");
+
+                    // Invalid request is a user error
+                    Err(Reply::bad_request("Invalid request!"))
+                }
+            };
+        let reply = reply.unwrap_or_else(|reply| reply);
+
+        stream
+            .write_all(&reply.packet(json_reply).as_bytes())
+            .map_err(|_| ServerError::SocketError(SOCKET_WRITE_ERROR))?;
+
+        match (command.as_str(), reply.is_success()) {
+            ("KILL", true) => Err(ServerError::Kill),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads a command's typed request from a JSON body, or from the older plain text encoding with
+/// `legacy`. An empty JSON body counts as a plain text one, so the output can still come from the
+/// query, unless the request has fields that can't be left out
+fn parse<T: DeserializeOwned>(
+    json: bool,
+    body: &str,
+    legacy: impl FnOnce() -> Result<T, String>,
+) -> Result<T, Reply> {
+    let from_json = |body| serde_json::from_str(body).map_err(|e| format!("Invalid request: {e}"));
+    match (json, body.trim().is_empty()) {
+        (true, false) => from_json(body),
+        (true, true) => from_json("{}").and_then(|_: T| legacy()),
+        (false, _) => legacy(),
+    }
+    .map_err(Reply::bad_request)
+}
+
+/// Works out the command a plain HTTP request (e.g. `GET /history?count=5`) is asking for, along
+/// with its body and the output from the `output` query parameter. Requests that don't match a
+/// route get back the response to send instead
+fn route(request: &Packet) -> Result<(String, String, Option<String>), Packet> {
+    let not_found = || Packet::new().method("404").body("No such route");
+    let (method, target) = request.target().ok_or_else(not_found)?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.trim_end_matches('/');
+
+    let routes: Vec<_> = ROUTES
+        .iter()
+        .filter(|(_, route_path, _)| *route_path == path)
+        .collect();
+    let Some((.., command)) = routes
+        .iter()
+        .find(|(route_method, ..)| route_method.eq_ignore_ascii_case(method))
+    else {
+        return Err(match routes.is_empty() {
+            true => not_found(),
+            // The path exists, just not for this method
+            false => {
+                let allowed: Vec<&str> = routes.iter().map(|(method, ..)| *method).collect();
+                Packet::new()
+                    .method("405")
+                    .header("Allow", &allowed.join(", "))
+                    .body(&format!("{path} only takes {}", allowed.join(" or ")))
+            }
+        });
+    };
+
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| socket_utils::percent_decode(key) == name)
+            .map(|(_, value)| socket_utils::percent_decode(value))
+    };
+
+    // Tools like curl tend to leave a newline at the end of the body
+    let body = request.body.trim_end();
+    let body = match *command {
+        // GET requests don't usually have a body, so the count can go in the query instead
+        "HISTORY" if body.is_empty() => param("count").unwrap_or_default(),
+        _ => body.to_string(),
+    };
+    Ok((command.to_string(), body, param("output")))
+}
+
+impl OutputState {
+    /// Creates a queue that starts at the `offset`th wallpaper (or a random one), so that
+    /// outputs don't all start out showing the same image.
+    ///
+    /// `weights` are only used (and only need to be loaded) in weighted mode
+    pub fn new(
+        wallpapers: &[String],
+        weights: &[u32],
+        offset: usize,
+        selection: SelectionMode,
+    ) -> Self {
+        if wallpapers.is_empty() {
+            return Self::default();
+        }
+
+        let mut state = Self::default();
+        match selection {
+            SelectionMode::Sequential => {
+                state.index = offset % wallpapers.len();
+                state.next_wallpaper = wallpapers[state.index].clone();
+            }
+            SelectionMode::Shuffle => state.next_wallpaper = state.draw(wallpapers),
+            SelectionMode::Weighted => {
+                state.next_wallpaper = pick_weighted(wallpapers, weights, "")
+            }
+        }
+        state
+    }
+
+    /// Moves the queued wallpaper to `current_wallpaper` and queues up the one after it.
+    ///
+    /// Internally increments `index`.
+    fn advance(&mut self, wallpapers: &[String], weights: &[u32], selection: SelectionMode) {
+        match selection {
+            SelectionMode::Sequential => {}
+            SelectionMode::Shuffle => {
+                self.show_next();
+                self.next_wallpaper = self.draw(wallpapers);
+                return;
+            }
+            SelectionMode::Weighted => {
+                self.show_next();
+                self.next_wallpaper = pick_weighted(wallpapers, weights, &self.current_wallpaper);
+                return;
+            }
+        }
+
+        // Files might have been added or removed since the last cycle, which shifts everything after
+        // them. Picking up from wherever the queued wallpaper ended up keeps the order going
+        if let Some(position) = wallpapers.iter().position(|w| *w == self.next_wallpaper) {
+            self.index = position;
+        }
+
+        // Change index until we're on a new wallpaper. This should only ever be a
+        // problem when multiple files have the same name or the directory grows in size
+        while wallpapers.len() > 1
+            && wallpapers[self.index % wallpapers.len()] == self.next_wallpaper
+        {
+            self.index += 1;
+        }
+
+        self.index %= wallpapers.len();
+
+        // Queue the next wallpaper
+        self.show_next();
+        self.next_wallpaper = wallpapers[self.index].clone();
+    }
+
+    /// Moves the queued wallpaper to `current_wallpaper`, and the current one to the history
+    fn show_next(&mut self) {
+        let previous = std::mem::replace(
+            &mut self.current_wallpaper,
+            std::mem::take(&mut self.next_wallpaper),
+        );
+
+        if std::mem::take(&mut self.rewinding) || previous.is_empty() {
+            return;
+        }
+        self.remember(previous);
+    }
+
+    /// Adds a wallpaper to the end of the history, dropping the oldest one if it's full
+    fn remember(&mut self, path: String) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(path);
+    }
+
+    /// Queues the previously shown wallpaper, taking it out of the history. Returns it, or `None` if
+    /// the history is empty
+    pub fn rewind(&mut self) -> Option<&str> {
+        let previous = self.history.pop_back()?;
+        let skipped = std::mem::replace(&mut self.next_wallpaper, previous);
+
+        // A wallpaper queued by an earlier `rewind()` came out of the history, so it goes back in.
+        // Anything else hasn't been shown yet, so it goes back in the bag for this round
+        if self.rewinding {
+            self.remember(skipped);
+        } else if self.shuffle_round.contains(&skipped) {
+            self.shuffle_bag.push(skipped);
+        }
+
+        self.rewinding = true;
+        Some(&self.next_wallpaper)
+    }
+
+    /// Queues `path` to be shown next, in place of whatever was queued
+    pub fn queue(&mut self, path: String) {
+        let skipped = std::mem::replace(&mut self.next_wallpaper, path);
+
+        // Undo a `rewind()` that hasn't been shown yet, or its wallpaper would be lost from the history
+        if std::mem::take(&mut self.rewinding) {
+            self.remember(skipped);
+        }
+    }
+
+    /// Takes the next wallpaper out of the shuffle bag, so that every wallpaper is shown once before
+    /// any of them is shown again. `wallpapers` must not be empty
+    fn draw(&mut self, wallpapers: &[String]) -> String {
+        self.reconcile_bag(wallpapers);
+
+        if self.shuffle_bag.is_empty() {
+            self.shuffle_bag = wallpapers.to_vec();
+            self.shuffle_bag.shuffle(&mut rand::rng());
+            self.shuffle_round = wallpapers.iter().cloned().collect();
+
+            // Don't let the new round start with what was just shown
+            let last = self.shuffle_bag.len() - 1;
+            if last > 0
+                && [&self.current_wallpaper, &self.next_wallpaper]
+                    .contains(&&self.shuffle_bag[last])
+            {
+                self.shuffle_bag.swap(last, rand::random_range(..last));
+            }
+            log::debug!("Reshuffled {} wallpapers", self.shuffle_bag.len());
+        }
+
+        self.shuffle_bag.pop().unwrap_or_default()
+    }
+
+    /// Brings the bag up to date with the directory: deleted files are dropped, and new ones are
+    /// slipped in at random spots among the ones that haven't been shown yet
+    fn reconcile_bag(&mut self, wallpapers: &[String]) {
+        let current: HashSet<&String> = wallpapers.iter().collect();
+        self.shuffle_bag.retain(|file| current.contains(file));
+        self.shuffle_round.retain(|file| current.contains(file));
+
+        // An empty round means the bag is about to be refilled with everything anyway
+        if self.shuffle_round.is_empty() {
+            return;
+        }
+        for file in wallpapers {
+            if self.shuffle_round.insert(file.clone()) {
+                let position = rand::random_range(..=self.shuffle_bag.len());
+                self.shuffle_bag.insert(position, file.clone());
             }
         }
-        Ok(())
     }
 }
 
-/// Ran by the child thread to periodically cycle wallpapers
+impl WallpaperData {
+    /// Replaces every output's queue with a fresh one over `wallpapers`. What each output is showing
+    /// and its history are kept, so `PREV` can still go back to wallpapers from before
+    pub fn reset_outputs(&mut self, outputs: Vec<String>, wallpapers: &[String], weights: &[u32]) {
+        let mut old_outputs = std::mem::take(&mut self.outputs);
+        self.outputs = outputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut state = OutputState::new(wallpapers, weights, i, self.selection);
+                if let Some(mut old) = old_outputs.remove(&name) {
+                    // A rewound wallpaper that wasn't shown yet goes back in the history
+                    if old.rewinding {
+                        let rewound = std::mem::take(&mut old.next_wallpaper);
+                        old.remember(rewound);
+                    }
+                    state.current_wallpaper = old.current_wallpaper;
+                    state.history = old.history;
+                }
+                (name, state)
+            })
+            .collect();
+    }
+
+    /// Adds queues for outputs that were plugged in and drops the ones for outputs that went away
+    fn sync_outputs(&mut self, outputs: Vec<String>, wallpapers: &[String], weights: &[u32]) {
+        if outputs.iter().eq(self.outputs.keys()) {
+            return;
+        }
+        log::info!("Outputs changed, now cycling wallpapers on: {outputs:?}");
+
+        let mut old_outputs = std::mem::take(&mut self.outputs);
+        for (i, name) in outputs.into_iter().enumerate() {
+            let state = old_outputs
+                .remove(&name)
+                .unwrap_or_else(|| OutputState::new(wallpapers, weights, i, self.selection));
+            self.outputs.insert(name, state);
+        }
+    }
+}
+
+/// Asks the backend which outputs it can set wallpapers on, falling back to `ALL_OUTPUTS`
+fn query_outputs(backend: &mut dyn WallpaperBackend) -> Vec<String> {
+    let mut outputs = backend.outputs().unwrap_or_else(|e| {
+        log::warn!("Could not list outputs, treating every output as one: {e}");
+        vec![]
+    });
+    outputs.sort();
+    outputs.dedup();
+
+    match outputs.is_empty() {
+        true => vec![ALL_OUTPUTS.to_string()],
+        false => outputs,
+    }
+}
+
+/// Weights of `wallpapers` for weighted mode. Nothing is read in the other modes, which don't need them
+pub(crate) fn selection_weights(
+    selection: SelectionMode,
+    directory: &Path,
+    wallpapers: &[String],
+) -> Vec<u32> {
+    match selection {
+        SelectionMode::Weighted => weights::load_weights(directory, wallpapers),
+        _ => vec![],
+    }
+}
+
+/// Picks a random wallpaper, with the odds of each one proportional to its weight.
 ///
-/// Internally increments `index`.
-fn cycle_wallpapers<'a>(
-    duration: u64,
-    child_trigger: &'a Arc<(Mutex<bool>, Condvar)>,
+/// `avoid` (the wallpaper being shown) is only picked again if nothing else has a weight. If nothing
+/// has a weight at all, every wallpaper is equally likely. `wallpapers` must not be empty
+fn pick_weighted(wallpapers: &[String], weights: &[u32], avoid: &str) -> String {
+    let roll = |skip_avoid: bool| -> Option<&String> {
+        let weight_of = |i: usize| match skip_avoid && wallpapers[i] == avoid {
+            true => 0,
+            false => weights.get(i).copied().unwrap_or(weights::DEFAULT_WEIGHT) as u64,
+        };
+
+        let total: u64 = (0..wallpapers.len()).map(weight_of).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = rand::random_range(..total);
+        (0..wallpapers.len()).find_map(|i| match roll < weight_of(i) {
+            true => Some(&wallpapers[i]),
+            false => {
+                roll -= weight_of(i);
+                None
+            }
+        })
+    };
+
+    roll(true)
+        .or_else(|| roll(false))
+        .unwrap_or_else(|| {
+            log::warn!("Every wallpaper has a weight of 0, picking one at random");
+            &wallpapers[rand::random_range(..wallpapers.len())]
+        })
+        .clone()
+}
+
+/// Shuts everything down after an error the cycling thread can't recover from, the same way `run()`
+/// would have, and exits the process
+fn terminate(backend: &SharedBackend, events: &SharedSubscribers, socket: &Path) -> ! {
+    log::error!("FATAL ERROR. Terminating...");
+    events.lock().unwrap().send(&Event::Shutdown);
+    backend.lock().unwrap().shutdown();
+    if let Err(e) = std::fs::remove_file(socket) {
+        log::error!("Failed to remove socket file: {e}");
+    }
+    std::process::exit(1);
+}
+
+/// Ran by the child thread to periodically cycle wallpapers.
+///
+/// Cycles the outputs named in `requests`, or every output if one of them is `None`.
+pub(crate) fn cycle_wallpapers<'a>(
+    requests: &[Option<String>],
     child_data: &'a Arc<Mutex<WallpaperData>>,
+    child_backend: &'a SharedBackend,
+    child_index: &'a SharedIndex,
+    child_events: &SharedSubscribers,
 ) -> Result<(), ServerError<'a>> {
+    // Without a watcher, the only way to notice changes is to rescan. This happens before taking
+    // the data lock so that slow (e.g. network mounted) directories don't block socket commands
+    if child_index.lock().unwrap().polling {
+        WallpaperIndex::rescan(child_index).map_err(|e| {
+            log::error!("{e}");
+            ServerError::FileError("Error in reading directory")
+        })?;
+        log::info!("Reloaded directory");
+    }
+    let (directory, wallpapers) = {
+        let index = child_index.lock().unwrap();
+        (index.directory.clone(), index.files.clone())
+    };
+
+    // Weights files are read every cycle (outside the lock as well), so edits to them apply right away
+    let selection = child_data.lock().unwrap().selection;
+    let weights = selection_weights(selection, &directory, &wallpapers);
+
     let mut data = child_data.lock().unwrap();
 
-    let wallpapers =
-        file_utils::get_directory_files(&PathBuf::from(&data.directory), data.recursive).map_err(
-            |e| {
-                log::error!("{e}");
-                ServerError::FileError("Error in reading directory")
-            },
-        )?;
+    // `SETDIR` swapped in a new index since we read it. It triggers a cycle of its own, so this one
+    // can just be dropped rather than mixing the old directory's files into the new queues
+    if child_index.lock().unwrap().directory != directory {
+        log::info!("Directory changed while cycling, skipping this cycle");
+        return Ok(());
+    }
 
-    log::info!("Reloaded directory");
+    let mut backend = child_backend.lock().unwrap();
 
     // If the wallpaper's directory is empty, we should return an error and leave the index unchanged
     if wallpapers.is_empty() {
         return Err(ServerError::FileError("Empty directory"));
     }
 
-    // Change index until we're on a new wallpaper. This should only ever be a
-    // problem when multiple files have the same name or the directory grows in size
-    while wallpapers[data.index % wallpapers.len()] == data.next_wallpaper {
-        match data.random {
-            true => data.index = rand::random_range(..wallpapers.len()),
-            false => data.index += 1,
-        }
+    let cycle_all = requests.contains(&None);
+    if cycle_all && !data.pinned_outputs {
+        let outputs = query_outputs(backend.as_mut());
+        data.sync_outputs(outputs, &wallpapers, &weights);
     }
 
-    data.index %= wallpapers.len();
-
-    // Queue the next wallpaper
-    data.current_wallpaper = data.next_wallpaper.clone();
-    data.next_wallpaper = wallpapers[data.index].clone();
+    let selection = data.selection;
+    let mut result = Ok(());
+    let mut changed = Vec::new();
+    for (name, output) in data.outputs.iter_mut() {
+        if !cycle_all && !requests.contains(&Some(name.clone())) {
+            continue;
+        }
 
-    log::info!("Queued wallpaper: {}", data.current_wallpaper);
+        output.advance(&wallpapers, &weights, selection);
+        log::info!(
+            "Queued wallpaper for output `{name}`: {}",
+            output.next_wallpaper
+        );
 
-    // Change wallpaper
-    log::info!("Setting wallpaper: {}", &data.current_wallpaper);
-    file_utils::hyprpaper_update(&data.current_wallpaper)
-        .map_err(|_| ServerError::HyprpaperError)?;
+        // Change wallpaper
+        log::info!(
+            "Setting wallpaper on output `{name}` with {}: {}",
+            backend.name(),
+            &output.current_wallpaper
+        );
+        if let Err(e) = backend
+            .preload(&output.current_wallpaper)
+            .and_then(|_| backend.apply(name, &output.current_wallpaper))
+        {
+            log::error!("{e}");
+            result = Err(ServerError::BackendError);
+            continue;
+        }
+        changed.push(Event::WallpaperChanged {
+            output: name.clone(),
+            path: output.current_wallpaper.clone(),
+        });
+    }
 
+    if let Err(e) = backend.unload() {
+        log::error!("{e}");
+        result = Err(ServerError::BackendError);
+    }
+    drop(backend);
     drop(data);
-    // Wait for trigger or timeout
+
+    // Sent once the locks are released, a slow subscriber shouldn't hold up commands
+    let mut events = child_events.lock().unwrap();
+    changed.iter().for_each(|event| events.send(event));
+
+    result
+}
+
+/// Blocks the child thread until either the trigger is pulled or the schedule's `duration` passes.
+/// While paused, only the trigger (or the end of a timed pause) wakes it up.
+///
+/// Returns the outputs that should be cycled.
+pub(crate) fn wait_for_trigger(
+    child_trigger: &Arc<(Mutex<Schedule>, Condvar)>,
+    child_events: &SharedSubscribers,
+) -> Vec<Option<String>> {
     let (lock, cvar) = &**child_trigger;
-    let triggered = lock.lock().unwrap();
-    let _ = cvar.wait_timeout(triggered, std::time::Duration::from_secs(duration));
 
-    Ok(())
+    let mut schedule = lock.lock().unwrap();
+    let mut timer_start = Instant::now();
+    let mut was_paused = schedule.paused;
+    loop {
+        if !schedule.requests.is_empty() {
+            return std::mem::take(&mut schedule.requests);
+        }
+
+        let now = Instant::now();
+        if schedule.resume_at.is_some_and(|resume_at| now >= resume_at) {
+            log::info!("Pause is over, resuming wallpaper cycling");
+            schedule.paused = false;
+            schedule.resume_at = None;
+            child_events.lock().unwrap().send(&Event::Resumed);
+        }
+
+        // The timer stands still while paused, and starts over once resumed
+        if was_paused && !schedule.paused {
+            timer_start = now;
+        }
+        was_paused = schedule.paused;
+
+        // Recomputed every time we wake up, since the duration can change while we wait. A duration
+        // too long to fit in an `Instant` never runs out
+        let deadline = timer_start.checked_add(schedule.duration);
+        if !schedule.paused && deadline.is_some_and(|deadline| now >= deadline) {
+            return vec![None];
+        }
+
+        let wake_at = match schedule.paused {
+            true => schedule.resume_at,
+            false => deadline,
+        };
+        schedule.next_cycle = wake_at;
+        schedule = match wake_at {
+            Some(wake_at) => cvar.wait_timeout(schedule, wake_at - now).unwrap().0,
+            None => cvar.wait(schedule).unwrap(),
+        };
+    }
 }
 
 // Server Error implementations
@@ -313,7 +1115,7 @@ fn cycle_wallpapers<'a>(
 #[derive(Debug)]
 pub enum ServerError<'a> {
     Kill,
-    HyprpaperError,
+    BackendError,
     RequestError(&'a str),
     SocketError(&'a str),
     FileError(&'a str),
@@ -323,7 +1125,7 @@ impl Display for ServerError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ServerError::Kill => f.write_str("Killed"),
-            ServerError::HyprpaperError => f.write_str("Hyprpaper crashed!"),
+            ServerError::BackendError => f.write_str("Wallpaper backend failed!"),
             ServerError::RequestError(msg) => f.write_str(msg),
             ServerError::SocketError(msg) => f.write_str(msg),
             ServerError::FileError(msg) => f.write_str(msg),
@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::file_utils::SortMode;
+
+use super::server::SelectionMode;
+
+/// Snapshot of the server's state, sent as JSON in reply to `STATUS`.
+///
+/// Times are in whole seconds so scripts don't have to parse humantime strings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub backend: String,
+    pub directory: String,
+    pub recursive: bool,
+    pub selection: SelectionMode,
+    pub sort: SortMode,
+    pub image_count: usize,
+    /// Whether the directory is watched for changes. If not, it's rescanned every cycle
+    pub watching: bool,
+    pub interval_seconds: u64,
+    /// `None` while paused
+    pub next_change_seconds: Option<u64>,
+    pub paused: bool,
+    /// Time left on a `pause --for`
+    pub resume_seconds: Option<u64>,
+    pub uptime_seconds: u64,
+    pub outputs: BTreeMap<String, OutputStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub current_wallpaper: String,
+    pub next_wallpaper: String,
+}
+
+/// Formats a number of seconds like `4m 12s`
+fn seconds(seconds: u64) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(seconds))
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let recursive = match self.recursive {
+            true => " (recursive)",
+            false => "",
+        };
+        let watching = match self.watching {
+            true => "watched for changes",
+            false => "rescanned every cycle",
+        };
+        let selection = self.selection.to_possible_value();
+        let sort = self.sort.to_possible_value();
+
+        writeln!(f, "Backend:     {}", self.backend)?;
+        writeln!(f, "Directory:   {}{recursive}", self.directory)?;
+        writeln!(f, "Images:      {} ({watching})", self.image_count)?;
+        writeln!(
+            f,
+            "Selection:   {}, sorted by {}",
+            selection.as_ref().map_or("", |value| value.get_name()),
+            sort.as_ref().map_or("", |value| value.get_name())
+        )?;
+        writeln!(f, "Interval:    {}", seconds(self.interval_seconds))?;
+        match (self.paused, self.resume_seconds, self.next_change_seconds) {
+            (true, Some(resume), _) => {
+                writeln!(f, "Next change: paused, resuming in {}", seconds(resume))?
+            }
+            (true, None, _) => writeln!(f, "Next change: paused")?,
+            (false, _, Some(next)) => writeln!(f, "Next change: in {}", seconds(next))?,
+            (false, _, None) => writeln!(f, "Next change: now")?,
+        }
+        write!(f, "Uptime:      {}", seconds(self.uptime_seconds))?;
+
+        for (name, output) in &self.outputs {
+            // The empty name is `ALL_OUTPUTS`
+            let name = match name.is_empty() {
+                true => "all",
+                false => name,
+            };
+            writeln!(f)?;
+            writeln!(f, "Output {name}:")?;
+            writeln!(f, "  Current:   {}", output.current_wallpaper)?;
+            write!(f, "  Next:      {}", output.next_wallpaper)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1418 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backends::{
+        recording::{Recording, RecordingBackend},
+        ALL_OUTPUTS,
+    },
+    constants::*,
+    output,
+    utils::{
+        file_index::WallpaperIndex,
+        file_utils::{self, ScanOptions, SortMode},
+        socket_utils::{self, Packet},
+    },
+};
+
+use super::{events::Event, messages::*, server::*};
+
+/// Minimal valid PNG header, enough for anything that sniffs file contents
+const PNG_MAGIC: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Directory under the system temp dir that is deleted when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("wonderwall-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    /// Creates an image file and returns its path
+    fn image(&self, name: &str) -> String {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(PNG_MAGIC)
+            .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates a server on `dir` that records what it applies instead of setting anything
+fn dry_run_server(dir: &TempDir, options: WallpaperOptions) -> (WallpaperServer, Recording) {
+    let backend = RecordingBackend::new(None).unwrap();
+    let recording = backend.recording();
+    let options = WallpaperOptions {
+        directory: dir.path().to_str().unwrap().to_string(),
+        duration: std::time::Duration::from_secs(600),
+        ..options
+    };
+    let server = WallpaperServer::with_backend(options, Box::new(backend)).unwrap();
+    (server, recording)
+}
+
+/// Cycles whatever the server's trigger asked for, the same way the cycling thread would
+fn cycle(server: &WallpaperServer, requests: &[Option<String>]) {
+    cycle_wallpapers(
+        requests,
+        &server.data,
+        &server.backend,
+        &server.index,
+        &server.events,
+    )
+    .unwrap();
+}
+
+/// Paths applied so far, ignoring outputs
+fn applied(recording: &Recording) -> Vec<String> {
+    recording
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Sends a command handler's reply as plain text through a socket pair and returns the parsed response
+fn respond(reply: Reply) -> Packet {
+    let (mut server_end, mut client_end) = UnixStream::pair().unwrap();
+    server_end
+        .write_all(&reply.packet(false).as_bytes())
+        .unwrap();
+    drop(server_end);
+    socket_utils::read_packet(&mut client_end).unwrap()
+}
+
+#[test]
+fn sequential_mode_shows_every_wallpaper_before_repeating() {
+    let dir = TempDir::new("sequential");
+    let mut images = vec![dir.image("a.png"), dir.image("b.png"), dir.image("c.png")];
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    for _ in 0..4 {
+        cycle(&server, &[None]);
+    }
+
+    let mut shown = applied(&recording);
+    assert_eq!(shown.len(), 4);
+    assert_eq!(shown[3], shown[0]);
+
+    shown.truncate(3);
+    shown.sort();
+    images.sort();
+    assert_eq!(shown, images);
+}
+
+#[test]
+fn random_mode_never_repeats_back_to_back() {
+    let dir = TempDir::new("random");
+    dir.image("a.png");
+    dir.image("b.png");
+    dir.image("c.png");
+    let options = WallpaperOptions {
+        selection: SelectionMode::Shuffle,
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    for _ in 0..20 {
+        cycle(&server, &[None]);
+    }
+
+    let shown = applied(&recording);
+    assert!(shown.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn backend_without_outputs_cycles_every_output_at_once() {
+    let dir = TempDir::new("all-outputs");
+    dir.image("a.png");
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    cycle(&server, &[None]);
+
+    let recording = recording.lock().unwrap();
+    assert_eq!(recording.len(), 1);
+    assert_eq!(recording[0].0, ALL_OUTPUTS);
+}
+
+#[test]
+fn next_only_cycles_the_requested_output() {
+    let dir = TempDir::new("next-output");
+    dir.image("a.png");
+    dir.image("b.png");
+    let options = WallpaperOptions {
+        outputs: vec!["DP-1".to_string(), "HDMI-A-1".to_string()],
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+    cycle(&server, &[None]);
+    recording.lock().unwrap().clear();
+
+    let response = respond(server.next(OutputRequest {
+        output: Some("DP-1".to_string()),
+    }));
+    assert!(response.method.contains("200"));
+
+    let requests = wait_for_trigger(&server.main_trigger, &server.events);
+    assert_eq!(requests, vec![Some("DP-1".to_string())]);
+    cycle(&server, &requests);
+
+    let recording = recording.lock().unwrap();
+    assert_eq!(recording.len(), 1);
+    assert_eq!(recording[0].0, "DP-1");
+}
+
+#[test]
+fn set_wp_shows_the_given_wallpaper_next() {
+    let dir = TempDir::new("setwp");
+    dir.image("a.png");
+    dir.image("b.png");
+    let custom = dir.image("custom/c.png");
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+    cycle(&server, &[None]);
+
+    respond(server.set_wp(SetWpRequest {
+        path: custom.clone(),
+        output: None,
+    }));
+    let requests = wait_for_trigger(&server.main_trigger, &server.events);
+    cycle(&server, &requests);
+
+    assert_eq!(applied(&recording).last(), Some(&custom));
+}
+
+#[test]
+fn set_wp_rejects_unknown_outputs() {
+    let dir = TempDir::new("setwp-unknown");
+    let image = dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let response = respond(server.set_wp(SetWpRequest {
+        path: image,
+        output: Some("DP-9".to_string()),
+    }));
+    assert!(response.method.contains("400"));
+}
+
+#[test]
+fn set_dir_switches_directory_and_flags() {
+    let dir = TempDir::new("setdir-old");
+    let old = dir.image("a.png");
+    let new_dir = TempDir::new("setdir-new");
+    let nested = new_dir.image("nested/b.png");
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+    cycle(&server, &[None]);
+
+    let new_path = new_dir.path().to_str().unwrap().to_string();
+    let response = respond(
+        server
+            .set_dir(SetDirRequest::from_legacy(&format!("true\n\n\n\nsize\n{new_path}")).unwrap()),
+    );
+    assert!(response.method.contains("200"));
+
+    {
+        let data = server.data.lock().unwrap();
+        assert_eq!(data.directory, new_path);
+        assert!(data.scan.recursive);
+        assert_eq!(data.scan.sort, SortMode::Size);
+        assert_eq!(data.selection, SelectionMode::Sequential);
+    }
+
+    let requests = wait_for_trigger(&server.main_trigger, &server.events);
+    cycle(&server, &requests);
+    assert_eq!(applied(&recording).last(), Some(&nested));
+
+    // The wallpaper from the old directory is still in the history
+    let response = respond(server.history(HistoryRequest::default()));
+    assert_eq!(response.body, old);
+}
+
+#[test]
+fn empty_directory_is_reported() {
+    let dir = TempDir::new("empty");
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let result = cycle_wallpapers(
+        &[None],
+        &server.data,
+        &server.backend,
+        &server.index,
+        &server.events,
+    );
+    assert!(matches!(
+        result,
+        Err(ServerError::FileError("Empty directory"))
+    ));
+    assert!(recording.lock().unwrap().is_empty());
+}
+
+#[test]
+fn non_images_are_skipped() {
+    let dir = TempDir::new("non-images");
+    let image = dir.image("a.png");
+    for (name, contents) in [
+        (".DS_Store", "junk"),
+        ("notes.txt", "hello"),
+        ("b.png.part", "\u{89}PNG"),
+        ("fake.png", "not really a png"),
+        ("empty.jpg", ""),
+    ] {
+        std::fs::write(dir.path().join(name), contents).unwrap();
+    }
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    cycle(&server, &[None]);
+    cycle(&server, &[None]);
+
+    assert_eq!(applied(&recording), vec![image.clone(), image]);
+}
+
+#[test]
+fn include_exclude_and_ignore_files_filter_the_scan() {
+    let dir = TempDir::new("filters");
+    let mut kept = vec![
+        dir.image("landscape/a.png"),
+        dir.image("landscape/keep-draft.png"),
+    ];
+    dir.image("landscape/x-draft.png");
+    dir.image("landscape/drafts/b.png");
+    dir.image("landscape/nsfw/c.png");
+    dir.image("phone/d.png");
+    std::fs::write(dir.path().join(".wonderwallignore"), "nsfw/\n*-draft.png\n").unwrap();
+    std::fs::write(
+        dir.path().join("landscape/.wonderwallignore"),
+        "!keep-draft.png\n",
+    )
+    .unwrap();
+
+    let options = WallpaperOptions {
+        scan: ScanOptions {
+            recursive: true,
+            include: vec!["landscape/**".to_string()],
+            exclude: vec!["**/drafts".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    for _ in 0..4 {
+        cycle(&server, &[None]);
+    }
+
+    let mut shown = applied(&recording);
+    shown.sort();
+    shown.dedup();
+    kept.sort();
+    assert_eq!(shown, kept);
+}
+
+/// Polls `condition` for a few seconds, since filesystem events arrive asynchronously
+fn eventually(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn watched_index_follows_created_removed_and_renamed_files() {
+    let dir = TempDir::new("watch");
+    let first = dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+    WallpaperIndex::watch(&server.index);
+    assert!(!server.index.lock().unwrap().polling);
+
+    let files = || server.index.lock().unwrap().files.clone();
+
+    let second = dir.image("b.png");
+    assert!(eventually(|| files().contains(&second)));
+
+    std::fs::remove_file(&first).unwrap();
+    assert!(eventually(|| !files().contains(&first)));
+
+    let renamed = dir.path().join("c.png").to_str().unwrap().to_string();
+    std::fs::rename(&second, &renamed).unwrap();
+    assert!(eventually(|| files() == vec![renamed.clone()]));
+
+    // Not an image, so it should never show up
+    std::fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(files(), vec![renamed]);
+}
+
+#[test]
+fn watcher_and_scans_agree_on_symlinks() {
+    let dir = TempDir::new("symlinks");
+    let real = dir.image("a.png");
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    let linked_file = dir.path().join("b.png");
+    std::os::unix::fs::symlink(&real, &linked_file).unwrap();
+    std::os::unix::fs::symlink(dir.path().join("sub"), dir.path().join("linked")).unwrap();
+    let in_real_dir = dir.image("sub/c.png");
+    let in_linked_dir = in_real_dir.replace("/sub/", "/linked/");
+
+    let options = ScanOptions {
+        recursive: true,
+        ..Default::default()
+    };
+    let root = dir.path().canonicalize().unwrap();
+    let scanned = file_utils::get_directory_files(&root, &options).unwrap();
+    assert_eq!(scanned, vec![real.clone(), in_real_dir]);
+    for path in [&real, linked_file.to_str().unwrap(), &in_linked_dir] {
+        assert_eq!(
+            file_utils::is_wallpaper(&root, Path::new(path), &options),
+            scanned.iter().any(|file| file == path),
+            "{path}"
+        );
+    }
+}
+
+#[test]
+fn overflowed_watcher_rescans_the_index() {
+    let dir = TempDir::new("overflow");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    // Files that showed up while the kernel was dropping events
+    let missed = dir.image("b.png");
+    let overflow =
+        notify::Event::new(notify::EventKind::Other).set_flag(notify::event::Flag::Rescan);
+    WallpaperIndex::handle_event(&server.index, overflow);
+
+    assert!(server.index.lock().unwrap().files.contains(&missed));
+}
+
+#[test]
+fn polled_index_is_rescanned_every_cycle() {
+    let dir = TempDir::new("poll");
+    let first = dir.image("a.png");
+    let options = WallpaperOptions {
+        poll: true,
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+    WallpaperIndex::watch(&server.index);
+    assert!(server.index.lock().unwrap().polling);
+
+    cycle(&server, &[None]);
+    std::fs::remove_file(&first).unwrap();
+    let second = dir.image("b.png");
+    cycle(&server, &[None]);
+    cycle(&server, &[None]);
+
+    assert_eq!(applied(&recording).last(), Some(&second));
+}
+
+#[test]
+fn sequential_mode_uses_natural_order() {
+    let dir = TempDir::new("natural");
+    let images = vec![
+        dir.image("img1.png"),
+        dir.image("IMG2.png"),
+        dir.image("img10.png"),
+    ];
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    for _ in 0..3 {
+        cycle(&server, &[None]);
+    }
+
+    assert_eq!(applied(&recording), images);
+}
+
+#[test]
+fn size_order_puts_smallest_files_first() {
+    let dir = TempDir::new("size");
+    let big = dir.image("a.png");
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&big)
+        .unwrap()
+        .write_all(&[0; 64])
+        .unwrap();
+    let small = dir.image("b.png");
+    let options = WallpaperOptions {
+        scan: ScanOptions {
+            sort: SortMode::Size,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    cycle(&server, &[None]);
+    cycle(&server, &[None]);
+
+    assert_eq!(applied(&recording), vec![small, big]);
+}
+
+#[test]
+fn watched_index_keeps_its_sort_order() {
+    let dir = TempDir::new("watch-sorted");
+    let grow = |path: &str, bytes: usize| {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(&vec![0; bytes])
+            .unwrap();
+    };
+    let medium = dir.image("a.png");
+    grow(&medium, 32);
+    let options = WallpaperOptions {
+        scan: ScanOptions {
+            sort: SortMode::Size,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, _) = dry_run_server(&dir, options);
+    WallpaperIndex::watch(&server.index);
+    let files = || server.index.lock().unwrap().files.clone();
+
+    // New files go straight to where they belong
+    let small = dir.image("b.png");
+    assert!(eventually(|| files() == vec![small.clone(), medium.clone()]));
+
+    // Files that are written to move
+    grow(&small, 64);
+    assert!(eventually(|| files() == vec![medium.clone(), small.clone()]));
+}
+
+#[test]
+fn sequential_order_survives_new_files() {
+    let dir = TempDir::new("stable-order");
+    let a = dir.image("a.png");
+    let c = dir.image("c.png");
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    cycle(&server, &[None]);
+    assert_eq!(
+        server.data.lock().unwrap().outputs[ALL_OUTPUTS].next_wallpaper,
+        c
+    );
+
+    // Lands between the current and the queued wallpaper, which shouldn't make us skip or repeat anything
+    let b = dir.image("b.png");
+    WallpaperIndex::rescan(&server.index).unwrap();
+    cycle(&server, &[None]);
+    cycle(&server, &[None]);
+    cycle(&server, &[None]);
+
+    assert_eq!(applied(&recording), vec![a.clone(), c, a, b]);
+}
+
+#[test]
+fn random_mode_shows_every_wallpaper_once_per_round() {
+    let dir = TempDir::new("shuffle");
+    let mut images: Vec<String> = (0..5).map(|i| dir.image(&format!("{i}.png"))).collect();
+    images.sort();
+    let options = WallpaperOptions {
+        selection: SelectionMode::Shuffle,
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    for _ in 0..15 {
+        cycle(&server, &[None]);
+    }
+
+    let shown = applied(&recording);
+    assert!(shown.windows(2).all(|pair| pair[0] != pair[1]));
+    for round in shown.chunks(5) {
+        let mut round = round.to_vec();
+        round.sort();
+        assert_eq!(round, images);
+    }
+}
+
+#[test]
+fn shuffle_bag_picks_up_new_files_mid_round() {
+    let dir = TempDir::new("shuffle-new");
+    let mut images = vec![dir.image("a.png"), dir.image("b.png"), dir.image("c.png")];
+    let options = WallpaperOptions {
+        selection: SelectionMode::Shuffle,
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    cycle(&server, &[None]);
+    images.push(dir.image("d.png"));
+    WallpaperIndex::rescan(&server.index).unwrap();
+    for _ in 0..3 {
+        cycle(&server, &[None]);
+    }
+
+    let mut shown = applied(&recording);
+    shown.sort();
+    assert_eq!(shown, images);
+}
+
+#[test]
+fn weighted_mode_follows_weights_files() {
+    let dir = TempDir::new("weighted");
+    let favorite = dir.image("favorite.png");
+    dir.image("hidden.png");
+    dir.image("drafts/a.png");
+    let other = dir.image("other/b.png");
+    std::fs::write(
+        dir.path().join(".wonderwallweights"),
+        "# Never show these\nhidden.png = 0\ndrafts/** = 0\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("other/.wonderwallweights"),
+        "weight = 0\nb.png = 1\n",
+    )
+    .unwrap();
+    let options = WallpaperOptions {
+        selection: SelectionMode::Weighted,
+        scan: ScanOptions {
+            recursive: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (server, recording) = dry_run_server(&dir, options);
+
+    for _ in 0..20 {
+        cycle(&server, &[None]);
+    }
+
+    // Only two wallpapers have a weight, and the same one is never picked twice in a row
+    let shown = applied(&recording);
+    assert!(shown.iter().all(|path| *path == favorite || *path == other));
+    assert!(shown.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn prev_goes_back_through_the_history() {
+    let dir = TempDir::new("prev");
+    let images = [dir.image("a.png"), dir.image("b.png"), dir.image("c.png")];
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let response = respond(server.prev(OutputRequest::default()));
+    assert!(response.method.contains("400"));
+
+    for _ in 0..3 {
+        cycle(&server, &[None]);
+    }
+
+    let response = respond(server.history(HistoryRequest::default()));
+    assert_eq!(response.body, format!("{}\n{}", images[1], images[0]));
+    let response = respond(server.history(HistoryRequest {
+        count: Some(1),
+        output: None,
+    }));
+    assert_eq!(response.body, images[1]);
+
+    let response = respond(server.prev(OutputRequest::default()));
+    assert!(response.method.contains("200"));
+    let requests = wait_for_trigger(&server.main_trigger, &server.events);
+    cycle(&server, &requests);
+
+    // Going back doesn't add the wallpaper we left to the history, and the queue carries on from there
+    let response = respond(server.history(HistoryRequest::default()));
+    assert_eq!(response.body, images[0]);
+    cycle(&server, &[None]);
+    assert_eq!(
+        applied(&recording),
+        vec![
+            images[0].clone(),
+            images[1].clone(),
+            images[2].clone(),
+            images[1].clone(),
+            images[2].clone()
+        ]
+    );
+}
+
+#[test]
+fn rewinds_that_werent_shown_yet_stay_in_the_history() {
+    let dir = TempDir::new("prev-twice");
+    let images = [dir.image("a.png"), dir.image("b.png"), dir.image("c.png")];
+    let (server, recording) = dry_run_server(&dir, WallpaperOptions::default());
+    for _ in 0..3 {
+        cycle(&server, &[None]);
+    }
+    let history = || {
+        let data = server.data.lock().unwrap();
+        Vec::from(data.outputs[ALL_OUTPUTS].history.clone())
+    };
+
+    // Two `PREV`s before the cycling thread gets to the first one
+    server.prev(OutputRequest::default());
+    server.prev(OutputRequest::default());
+    assert_eq!(history(), vec![images[1].clone()]);
+    cycle(&server, &[None]);
+    assert_eq!(applied(&recording).last(), Some(&images[0]));
+
+    // A `SETWP` in place of a `PREV` puts the rewound wallpaper back, and the wallpaper it replaces
+    // goes in the history as usual
+    server.prev(OutputRequest::default());
+    server.set_wp(SetWpRequest {
+        path: images[2].clone(),
+        output: None,
+    });
+    cycle(&server, &[None]);
+    assert_eq!(applied(&recording).last(), Some(&images[2]));
+    assert_eq!(history(), vec![images[1].clone(), images[0].clone()]);
+}
+
+#[test]
+fn history_is_bounded() {
+    let dir = TempDir::new("history-length");
+    dir.image("a.png");
+    dir.image("b.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    for _ in 0..HISTORY_LENGTH + 10 {
+        cycle(&server, &[None]);
+    }
+
+    let data = server.data.lock().unwrap();
+    assert_eq!(data.outputs[ALL_OUTPUTS].history.len(), HISTORY_LENGTH);
+}
+
+/// Runs `wait_for_trigger` on another thread, so tests can check whether it's still blocked
+fn wait_in_background(server: &WallpaperServer) -> std::sync::mpsc::Receiver<Vec<Option<String>>> {
+    let trigger = server.main_trigger.clone();
+    let events = server.events.clone();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || sender.send(wait_for_trigger(&trigger, &events)));
+    receiver
+}
+
+#[test]
+fn paused_server_only_cycles_on_request() {
+    let dir = TempDir::new("pause");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+    server.set_interval(std::time::Duration::from_millis(1));
+
+    let response = respond(server.pause_cycling(PauseRequest::default()));
+    assert!(response.method.contains("200"));
+    assert!(server.status().paused);
+
+    // The timer would have gone off almost right away if we weren't paused
+    let woken = wait_in_background(&server);
+    let timeout = std::time::Duration::from_millis(200);
+    assert!(woken.recv_timeout(timeout).is_err());
+
+    // Asking for a wallpaper still works
+    server.trigger(Some(ALL_OUTPUTS.to_string()));
+    assert_eq!(
+        woken.recv_timeout(timeout).unwrap(),
+        vec![Some(ALL_OUTPUTS.to_string())]
+    );
+
+    let woken = wait_in_background(&server);
+    assert!(woken.recv_timeout(timeout).is_err());
+    let response = respond(server.toggle_cycling());
+    assert_eq!(response.body, "Resumed wallpaper cycling");
+    assert_eq!(woken.recv_timeout(timeout).unwrap(), vec![None]);
+}
+
+#[test]
+fn toggles_at_the_same_time_are_never_lost() {
+    let dir = TempDir::new("toggle-race");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    // An even number of toggles always ends up where it started
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    server.toggle_cycling();
+                }
+            });
+        }
+    });
+    assert!(!server.status().paused);
+}
+
+#[test]
+fn timed_pause_resumes_on_its_own() {
+    let dir = TempDir::new("pause-for");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+    server.set_interval(std::time::Duration::from_millis(1));
+
+    let response = respond(server.pause_cycling(PauseRequest::from_legacy("100ms")));
+    assert!(response.method.contains("200"));
+
+    let woken = wait_in_background(&server);
+    let requests = woken
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(requests, vec![None]);
+    assert!(!server.status().paused);
+
+    let response = respond(server.pause_cycling(PauseRequest::from_legacy("soon")));
+    assert!(response.method.contains("400"));
+}
+
+#[test]
+fn pauses_too_long_to_keep_track_of_are_rejected() {
+    let dir = TempDir::new("pause-overflow");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let response = respond(server.pause_cycling(PauseRequest {
+        duration: Some("300000000000y".to_string()),
+    }));
+    assert_eq!(response.status(), Some(400));
+    assert!(!server.status().paused);
+
+    // The server still works afterwards
+    let response = respond(server.get_status());
+    assert_eq!(response.status(), Some(200));
+}
+
+#[test]
+fn durations_too_long_to_keep_track_of_are_rejected() {
+    let dir = TempDir::new("duration-overflow");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let response = respond(server.set_duration(SetDurationRequest {
+        duration: "300000000000y".to_string(),
+    }));
+    assert_eq!(response.status(), Some(400));
+    assert_eq!(server.interval(), std::time::Duration::from_secs(600));
+
+    // Even one that slips through just never runs out
+    server.set_interval(std::time::Duration::MAX);
+    let waiting = wait_in_background(&server);
+    assert!(waiting
+        .recv_timeout(std::time::Duration::from_millis(100))
+        .is_err());
+    server.trigger(None);
+    assert_eq!(
+        waiting.recv_timeout(std::time::Duration::from_secs(1)),
+        Ok(vec![None])
+    );
+    let response = respond(server.get_status());
+    assert_eq!(response.status(), Some(200));
+}
+
+#[test]
+fn duration_can_be_changed_while_waiting() {
+    let dir = TempDir::new("duration");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let woken = wait_in_background(&server);
+    let timeout = std::time::Duration::from_secs(5);
+    assert!(woken
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .is_err());
+
+    // The time already waited counts, so this goes off right away
+    let response = respond(server.set_duration(SetDurationRequest {
+        duration: "100ms".to_string(),
+    }));
+    assert_eq!(response.body, "Wallpaper will now change every 100ms");
+    assert_eq!(woken.recv_timeout(timeout).unwrap(), vec![None]);
+
+    let response = respond(server.get_duration());
+    assert_eq!(response.body, "100ms");
+
+    for invalid in ["0s", "soon"] {
+        let response = respond(server.set_duration(SetDurationRequest {
+            duration: invalid.to_string(),
+        }));
+        assert!(response.method.contains("400"));
+    }
+    assert_eq!(server.interval(), std::time::Duration::from_millis(100));
+}
+
+#[test]
+fn status_reports_a_snapshot_as_json() {
+    let dir = TempDir::new("status");
+    let first = dir.image("a.png");
+    let second = dir.image("b.png");
+    let options = WallpaperOptions {
+        outputs: vec!["DP-1".to_string()],
+        ..Default::default()
+    };
+    let (server, _) = dry_run_server(&dir, options);
+    cycle(&server, &[None]);
+    server.pause(Some(std::time::Duration::from_secs(60)));
+
+    let response = respond(server.get_status());
+    assert!(response.method.contains("200"));
+    let status: super::status::Status = serde_json::from_str(&response.body).unwrap();
+
+    assert_eq!(status.backend, "dry-run");
+    assert_eq!(status.image_count, 2);
+    assert_eq!(status.selection, SelectionMode::Sequential);
+    assert_eq!(status.interval_seconds, 600);
+    assert!(status.paused);
+    assert!(status.resume_seconds.is_some_and(|seconds| seconds <= 60));
+    assert_eq!(status.next_change_seconds, None);
+    assert_eq!(status.outputs["DP-1"].current_wallpaper, first);
+    assert_eq!(status.outputs["DP-1"].next_wallpaper, second);
+
+    // The human readable version has everything in it too
+    let text = status.to_string();
+    assert!(text.contains("paused, resuming in"));
+    assert!(text.contains(&format!("Current:   {first}")));
+}
+
+#[test]
+fn packets_sent_in_pieces_are_read_in_full() {
+    let (mut server_end, mut client_end) = UnixStream::pair().unwrap();
+    let body = "a".repeat(200 * 1024);
+    let bytes = Packet::new()
+        .header("WallpaperControl", "SETWP")
+        .body(&body)
+        .as_bytes();
+
+    // A slow writer, with pauses between pieces that split the headers and body
+    let writer = std::thread::spawn(move || {
+        for piece in bytes.chunks(64 * 1024 + 7) {
+            client_end.write_all(piece).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    });
+
+    let request = socket_utils::read_packet(&mut server_end).unwrap();
+    writer.join().unwrap();
+    assert_eq!(request.headers["WallpaperControl"], "SETWP");
+    assert_eq!(request.body, body);
+}
+
+#[test]
+fn chunked_bodies_are_decoded() {
+    let dir = TempDir::new("chunked");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let send = |server: &WallpaperServer, bytes: &'static [u8]| {
+        let (server_end, mut client_end) = UnixStream::pair().unwrap();
+        // Split mid chunk, so the rest has to be waited for
+        let writer = std::thread::spawn(move || {
+            // Bad requests are answered before the rest is sent
+            for piece in bytes.chunks(bytes.len() - 5) {
+                let _ = client_end.write_all(piece);
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            client_end
+        });
+        let _ = server.handle_stream(server_end);
+        socket_utils::read_packet(&mut writer.join().unwrap()).unwrap()
+    };
+
+    // What curl sends for `echo 15m | curl -T - --unix-socket <socket> http://localhost/duration`
+    let response = send(
+        &server,
+        b"PUT /duration HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+          2;ext=1\r\n15\r\n2\r\nm\n\r\n0\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(
+        server.get_duration().message,
+        humantime::format_duration(std::time::Duration::from_secs(900)).to_string()
+    );
+
+    let response = send(
+        &server,
+        b"PUT /duration HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\n15m\r\n0\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(400));
+
+    let response = send(
+        &server,
+        b"PUT /duration HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n15m",
+    );
+    assert_eq!(response.status(), Some(501));
+}
+
+#[test]
+fn bad_requests_get_an_error_instead_of_a_panic() {
+    let dir = TempDir::new("bad-requests");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let send = |server: &WallpaperServer, bytes: &[u8]| {
+        let (server_end, mut client_end) = UnixStream::pair().unwrap();
+        client_end.write_all(bytes).unwrap();
+        let _ = server.handle_stream(server_end);
+        socket_utils::read_packet(&mut client_end).unwrap()
+    };
+
+    let not_utf8 = send(
+        &server,
+        b"POST / HTTP/1.1\r\nWallpaperControl: SETWP\r\nContent-Length: 3\r\n\r\n\xff\xfe\xfd",
+    );
+    assert!(not_utf8.method.contains("400"));
+
+    let too_large = send(
+        &server,
+        b"POST / HTTP/1.1\r\nWallpaperControl: SETWP\r\nContent-Length: 999999999\r\n\r\n",
+    );
+    assert!(too_large.method.contains("413"));
+
+    // Older clients don't send a length at all
+    let legacy = send(
+        &server,
+        b"POST / HTTP/1.1\r\nWallpaperControl: PING\r\n\r\n",
+    );
+    assert!(legacy.method.contains("200"));
+    assert_eq!(legacy.body.trim(), "pong");
+}
+
+#[test]
+fn plain_http_requests_are_routed_to_commands() {
+    let dir = TempDir::new("routes");
+    let first = dir.image("a.png");
+    dir.image("b.png");
+    let options = WallpaperOptions {
+        outputs: vec!["DP-1".to_string()],
+        ..Default::default()
+    };
+    let (server, _) = dry_run_server(&dir, options);
+    cycle(&server, &[None]);
+
+    let send = |server: &WallpaperServer, request: &str| {
+        let (server_end, mut client_end) = UnixStream::pair().unwrap();
+        client_end.write_all(request.as_bytes()).unwrap();
+        let _ = server.handle_stream(server_end);
+        socket_utils::read_packet(&mut client_end).unwrap()
+    };
+
+    // What curl sends for `curl --unix-socket <socket> http://localhost/wallpaper?output=DP-1`
+    let response = send(
+        &server,
+        "GET /wallpaper?output=DP-1 HTTP/1.1\r\nHost: localhost\r\nUser-Agent: curl/8.0\r\nAccept: */*\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(response.body, first);
+
+    // Escaped query strings are decoded before matching
+    let response = send(
+        &server,
+        "GET /wallpaper?%6Futput=DP%2d1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(response.body, first);
+    assert_eq!(socket_utils::percent_decode("a+b%20c%2"), "a b c%2");
+    assert_eq!(socket_utils::percent_decode("%+1%zz%C3%A9"), "% 1%zzé");
+
+    let response = send(
+        &server,
+        "PUT /duration HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\n90s\n",
+    );
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(server.interval(), std::time::Duration::from_secs(90));
+
+    // Plain seconds, same as on the command line
+    let response = send(
+        &server,
+        "PUT /duration HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\n900",
+    );
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(server.interval(), std::time::Duration::from_secs(900));
+
+    let response = send(
+        &server,
+        "POST /next?output=HDMI-A-1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(400));
+
+    let response = send(
+        &server,
+        "DELETE /wallpaper HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(405));
+    assert_eq!(response.get_header("Allow"), Some("GET, PUT"));
+
+    let response = send(
+        &server,
+        "GET /wallpapers HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(response.status(), Some(404));
+}
+
+#[test]
+fn setdir_from_the_oldest_clients_still_works() {
+    let dir = TempDir::new("oldest-setdir");
+    dir.image("a.png");
+    let new_dir = TempDir::new("oldest-setdir-new");
+    let b = new_dir.image("b.png");
+    let c = new_dir.image("nested/c.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    // Exactly what the first release of the client sent for `setdir --recursive --random <path>`
+    let new_path = new_dir.path().to_str().unwrap();
+    let request =
+        format!("POST / HTTP/1.1\r\nWallpaperControl: SETDIR\r\n\r\ntrue\ntrue\n{new_path}");
+    let (server_end, mut client_end) = UnixStream::pair().unwrap();
+    client_end.write_all(request.as_bytes()).unwrap();
+    let _ = server.handle_stream(server_end);
+    let response = socket_utils::read_packet(&mut client_end).unwrap();
+    assert_eq!(response.status(), Some(200));
+    assert_eq!(server.data.lock().unwrap().directory, new_path);
+    assert_eq!(
+        server.data.lock().unwrap().selection,
+        SelectionMode::Shuffle
+    );
+    assert_eq!(server.index.lock().unwrap().files, vec![b, c]);
+
+    // and for a plain `setdir <path>`
+    let request = SetDirRequest::from_legacy(&format!("\n\n{new_path}")).unwrap();
+    assert_eq!(request.path, new_path);
+    assert!(!request.recursive);
+    assert_eq!(request.selection, SelectionMode::Sequential);
+}
+
+#[test]
+fn json_requests_are_validated_and_get_typed_replies() {
+    let dir = TempDir::new("json");
+    dir.image("a.png");
+    let new_dir = TempDir::new("json-new");
+    let nested = new_dir.image("nested/b.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+    cycle(&server, &[None]);
+
+    let send = |server: &WallpaperServer, command: &str, body: &str| {
+        let (server_end, mut client_end) = UnixStream::pair().unwrap();
+        let request = Packet::new()
+            .command(command)
+            .header("Content-Type", "application/json")
+            .body(body);
+        client_end.write_all(&request.as_bytes()).unwrap();
+        let _ = server.handle_stream(server_end);
+        let response = socket_utils::read_packet(&mut client_end).unwrap();
+        let reply: Reply = serde_json::from_str(&response.body).unwrap();
+        (response.status(), reply)
+    };
+
+    let new_path = new_dir.path().to_str().unwrap().to_string();
+    let (status, _) = send(
+        &server,
+        "SETDIR",
+        &format!(r#"{{"path": "{new_path}", "recursive": true, "selection": "shuffle"}}"#),
+    );
+    assert_eq!(status, Some(200));
+    assert_eq!(
+        server.data.lock().unwrap().selection,
+        SelectionMode::Shuffle
+    );
+    assert_eq!(server.index.lock().unwrap().files, vec![nested.clone()]);
+
+    let (status, reply) = send(&server, "GETDIR", "");
+    assert_eq!(status, Some(200));
+    assert_eq!(reply.data["directory"], new_path);
+
+    // Typos and wrong types are caught instead of being quietly ignored
+    let (status, reply) = send(&server, "SETDIR", r#"{"path": "/", "recursve": true}"#);
+    assert_eq!(status, Some(400));
+    assert!(reply.message.contains("unknown field `recursve`"));
+    let (status, reply) = send(&server, "HISTORY", r#"{"count": "ten"}"#);
+    assert_eq!(status, Some(400));
+    assert!(reply.message.contains("invalid type"));
+
+    // Required fields can't be left out, not even by sending nothing at all
+    for body in ["", "{}"] {
+        let (status, reply) = send(&server, "SETWP", body);
+        assert_eq!(status, Some(400));
+        assert!(reply.message.contains("missing field `path`"));
+    }
+
+    // Only files that can be read are queued
+    let missing = format!("{new_path}/missing.png");
+    for path in ["", " ", &missing, &new_path] {
+        let (status, _) = send(&server, "SETWP", &format!(r#"{{"path": "{path}"}}"#));
+        assert_eq!(status, Some(400), "`{path}` was queued");
+    }
+    let (status, _) = send(&server, "SETWP", &format!(r#"{{"path": "{nested}"}}"#));
+    assert_eq!(status, Some(200));
+}
+
+#[test]
+fn subscribers_are_sent_events_as_json_lines() {
+    let dir = TempDir::new("events");
+    let first = dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    let (server_end, mut client_end) = UnixStream::pair().unwrap();
+    client_end
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    server.handle_stream(server_end).unwrap();
+
+    let mut reader = BufReader::new(client_end);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    let head = Packet::from_bytes(head.into_bytes()).unwrap();
+    assert_eq!(head.status(), Some(200));
+    assert_eq!(
+        head.get_header("Content-Type"),
+        Some("application/x-ndjson")
+    );
+
+    cycle(&server, &[None]);
+    server.pause(Some(std::time::Duration::from_secs(60)));
+    server.resume();
+    // Not paused anymore, so nothing to report
+    server.resume();
+    server.events.lock().unwrap().send(&Event::Shutdown);
+
+    let events: Vec<Event> = reader
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .take(4)
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            Event::WallpaperChanged {
+                output: ALL_OUTPUTS.to_string(),
+                path: first,
+            },
+            Event::Paused {
+                resume_seconds: Some(60),
+            },
+            Event::Resumed,
+            Event::Shutdown,
+        ]
+    );
+}
+
+#[test]
+fn subscribers_are_capped() {
+    let dir = TempDir::new("events-cap");
+    dir.image("a.png");
+    let (server, _) = dry_run_server(&dir, WallpaperOptions::default());
+
+    // Returns the client's end, with the response still unread
+    let subscribe = || {
+        let (server_end, mut client_end) = UnixStream::pair().unwrap();
+        client_end
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let _ = server.handle_stream(server_end);
+        client_end
+    };
+
+    let mut subscribers: Vec<_> = (0..MAX_SUBSCRIBERS).map(|_| subscribe()).collect();
+    let response = socket_utils::read_packet(&mut subscribe()).unwrap();
+    assert_eq!(response.status(), Some(503));
+
+    // Hanging up makes room again, even before the next event
+    subscribers.pop();
+    let mut reader = BufReader::new(subscribe());
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+    assert!(status_line.contains("200"));
+}
+
+#[test]
+fn socket_path_defaults_to_the_runtime_dir_and_is_made_absolute() {
+    assert_eq!(
+        socket_utils::socket_path_in(Some("/run/user/1000".into())),
+        PathBuf::from("/run/user/1000/wonderwall.sock")
+    );
+
+    // Without a runtime dir, every user still gets their own socket
+    let fallback = std::env::temp_dir().join(format!("wonderwall-{}.sock", nix::unistd::getuid()));
+    assert_eq!(socket_utils::socket_path_in(None), fallback);
+    assert_eq!(socket_utils::socket_path_in(Some("".into())), fallback);
+
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(
+        socket_utils::resolve_socket_path(Some("rel.sock".into())).unwrap(),
+        cwd.join("rel.sock")
+    );
+    assert_eq!(
+        socket_utils::resolve_socket_path(Some("/tmp/abs.sock".into())).unwrap(),
+        PathBuf::from("/tmp/abs.sock")
+    );
+    assert!(socket_utils::resolve_socket_path(None)
+        .unwrap()
+        .is_absolute());
+}
+
+#[test]
+fn stuck_clients_dont_hold_up_the_server() {
+    let dir = TempDir::new("stuck");
+    dir.image("a.png");
+    let socket = dir.path().join("test.sock");
+    let options = WallpaperOptions {
+        socket: socket.clone(),
+        ..Default::default()
+    };
+    let (mut server, _) = dry_run_server(&dir, options);
+    let running = std::thread::spawn(move || server.run().map_err(|e| e.to_string()));
+
+    let connect = || loop {
+        if let Ok(stream) = UnixStream::connect(&socket) {
+            break stream;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+
+    // Connects, then never sends anything
+    let mut stuck = vec![connect()];
+    let response = socket_utils::send_request("PING", "", &socket).unwrap();
+    assert_eq!(response.body, "pong");
+
+    // Past the limit, clients are turned away instead of piling up
+    stuck.extend((1..MAX_CONNECTIONS).map(|_| connect()));
+    let mut turned_away = connect();
+    let response = socket_utils::read_packet(&mut turned_away).unwrap();
+    assert_eq!(response.status(), Some(503));
+
+    // Hanging up frees the slots again
+    stuck.truncate(1);
+    let kill = loop {
+        let response = socket_utils::send_request("KILL", "", &socket).unwrap();
+        if response.status != 503 {
+            break response;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(kill.status, 200);
+
+    drop(stuck);
+    running.join().unwrap().unwrap();
+    assert!(!socket.exists());
+}
+
+#[test]
+fn client_exit_codes_and_output_follow_the_reply() {
+    use std::process::ExitCode;
+
+    // What gets written to stdout and stderr, and the exit code
+    let print = |result: Result<socket_utils::Response, socket_utils::ClientError>, json: bool| {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let code = output::write_reply(result, json, &mut out, &mut err);
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).unwrap();
+        (code, text(out), text(err))
+    };
+    let response = |status: u16, body: &str, json: bool| {
+        Ok(socket_utils::Response {
+            status,
+            body: body.to_string(),
+            json,
+        })
+    };
+    let typed = r#"{"message":"/walls/a.png","data":{"wallpapers":{"":"/walls/a.png"}}}"#;
+
+    // Successes go to stdout, failures to stderr
+    assert_eq!(
+        print(response(200, typed, true), false),
+        (
+            ExitCode::SUCCESS,
+            "/walls/a.png\n".to_string(),
+            String::new()
+        )
+    );
+    assert_eq!(
+        print(response(400, "Unknown output `DP-9`", false), false),
+        (
+            ExitCode::from(EXIT_REJECTED),
+            String::new(),
+            "Unknown output `DP-9`\n".to_string()
+        )
+    );
+    let (code, out, err) = print(response(500, "Backend fell over", false), false);
+    assert_eq!(code, ExitCode::from(EXIT_SERVER_ERROR));
+    assert!(out.is_empty() && err.contains("Backend fell over"));
+
+    let refused = || {
+        Err(socket_utils::ClientError::Connection(
+            std::io::ErrorKind::NotFound.into(),
+        ))
+    };
+    let (code, out, err) = print(refused(), false);
+    assert_eq!(code, ExitCode::from(EXIT_CONNECTION_ERROR));
+    assert!(out.is_empty() && err.contains("Is the server running?"));
+
+    let garbled = || Err(socket_utils::ClientError::Protocol("no status".to_string()));
+    let (code, out, err) = print(garbled(), false);
+    assert_eq!(code, ExitCode::from(EXIT_PROTOCOL_ERROR));
+    assert!(out.is_empty() && err.contains("no status"));
+
+    // `--json` always prints one object to stdout, with the same exit codes
+    let parse = |out: &str| serde_json::from_str::<serde_json::Value>(out).unwrap();
+    let (code, out, err) = print(response(200, typed, true), true);
+    assert_eq!(code, ExitCode::SUCCESS);
+    assert!(err.is_empty());
+    assert_eq!(
+        parse(&out),
+        serde_json::json!({
+            "status": 200,
+            "message": "/walls/a.png",
+            "data": {"wallpapers": {"": "/walls/a.png"}},
+        })
+    );
+
+    let (code, out, err) = print(
+        response(400, r#"{"message":"Nope","data":{"x":1}}"#, true),
+        true,
+    );
+    assert_eq!(code, ExitCode::from(EXIT_REJECTED));
+    assert!(err.is_empty());
+    assert_eq!(
+        parse(&out),
+        serde_json::json!({"status": 400, "message": "Nope", "data": null})
+    );
+
+    for (result, expected) in [
+        (refused(), EXIT_CONNECTION_ERROR),
+        (garbled(), EXIT_PROTOCOL_ERROR),
+    ] {
+        let (code, out, err) = print(result, true);
+        assert_eq!(code, ExitCode::from(expected));
+        assert!(err.is_empty());
+        let out = parse(&out);
+        assert_eq!(out["status"], serde_json::Value::Null);
+        assert_eq!(out["data"], serde_json::Value::Null);
+        assert!(out["message"].as_str().unwrap().contains("Ran into error"));
+    }
+}
+
+#[test]
+fn start_refuses_to_replace_something_that_isnt_a_socket() {
+    let dir = TempDir::new("not-a-socket");
+    let notes = dir.path().join("notes.txt");
+    std::fs::write(&notes, "important").unwrap();
+
+    let options = WallpaperOptions {
+        directory: dir.path().to_str().unwrap().to_string(),
+        socket: notes.clone(),
+        ..Default::default()
+    };
+    assert!(WallpaperServer::new(options).is_err());
+    assert_eq!(std::fs::read_to_string(&notes).unwrap(), "important");
+}